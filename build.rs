@@ -30,8 +30,8 @@ fn main() {
         .expect("Failed to generate rust code from proto");
 
     // skip build of webapp when specified environment variable SKIP_WEBAPP
-    if let Some(_) = option_env!("SKIP_BUILD_WEBAPP") {
-        println!("cargo:warning={}", "skipped build process of webapp");
+    if option_env!("SKIP_BUILD_WEBAPP").is_some() {
+        println!("cargo:warning=skipped build process of webapp");
         return;
     }
 
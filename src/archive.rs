@@ -0,0 +1,422 @@
+use crate::{
+    manage::{get_cancelled_error, CopyProgress, ProgressHandler},
+    pool::FsPool,
+    resolve::{resolve_path_from, SymlinkPolicy},
+    trash::TRASH_DIR_NAME,
+};
+use anyhow::anyhow;
+use axum::body::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use futures::Stream;
+use log::{debug, trace};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+type AnyError = anyhow::Error;
+
+const ARCHIVE_CHUNK_SIZE: usize = 64 * 1024;
+const ARCHIVE_CHANNEL_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar" => Ok(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            _ => Err(anyhow!("unsupported archive format {:?}", s)),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn get_mime(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// Item to be archived, all files under it are added when it's a dir
+#[derive(Debug, Clone)]
+pub struct ArchiveItem {
+    pub path: PathBuf,
+    // name in archive, components separated by '/'
+    pub name: String,
+}
+
+impl ArchiveItem {
+    pub fn new(path: PathBuf, name: &str) -> Self {
+        ArchiveItem {
+            path,
+            name: name.to_string(),
+        }
+    }
+}
+
+// sync writer of the archive builders, sends buffered data to the response body
+struct ChannelWriter {
+    sender: Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn new(sender: Sender<io::Result<Bytes>>) -> Self {
+        ChannelWriter {
+            sender,
+            buffer: Vec::with_capacity(ARCHIVE_CHUNK_SIZE),
+        }
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::replace(&mut self.buffer, Vec::with_capacity(ARCHIVE_CHUNK_SIZE));
+        // receiver is dropped when client disconnected, so stop archiving
+        self.sender
+            .blocking_send(Ok(Bytes::from(data)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "archive receiver dropped"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= ARCHIVE_CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+/// Get items to archive in dir, the dir itself is archived when no paths selected
+pub fn get_archive_items(
+    root: &Path,
+    full_path: &Path,
+    paths: &[String],
+    symlinks: SymlinkPolicy,
+) -> Result<Vec<ArchiveItem>, AnyError> {
+    // without selected paths, the dir itself is archived with its name as the top dir
+    if paths.is_empty() {
+        let name = match full_path == root {
+            true => String::new(),
+            false => full_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        };
+        return Ok(vec![ArchiveItem::new(full_path.to_path_buf(), &name)]);
+    }
+    // selected paths are relative to the dir, and named relatively in archive
+    let mut items = vec![];
+    for path in paths {
+        let item_path = resolve_path_from(root, full_path, path, symlinks)?;
+        let name = item_path
+            .strip_prefix(full_path)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        items.push(ArchiveItem::new(item_path, &name));
+    }
+    Ok(items)
+}
+
+/// Get default file name of archive of dir, named after the dir or the app for root
+pub fn get_archive_name(root: &Path, full_path: &Path, format: ArchiveFormat) -> String {
+    let base_name = match full_path == root {
+        true => env!("CARGO_PKG_NAME").to_string(),
+        false => full_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    };
+    format!("{}.{}", base_name, format.get_extension())
+}
+
+// progress of archiving, the entry at skip is never added, e.g. the archive being saved
+struct ArchiveProgress<'a> {
+    skip: Option<PathBuf>,
+    progress: CopyProgress,
+    on_progress: &'a mut ProgressHandler<'a>,
+    cancelled: bool,
+}
+
+impl<'a> ArchiveProgress<'a> {
+    fn new(skip: Option<PathBuf>, on_progress: &'a mut ProgressHandler<'a>) -> Self {
+        ArchiveProgress {
+            skip,
+            progress: CopyProgress::default(),
+            on_progress,
+            cancelled: false,
+        }
+    }
+
+    fn report(&mut self) -> io::Result<()> {
+        if !(self.on_progress)(&self.progress) {
+            // Interrupted is retried by io::copy, so cancelling is marked and checked later
+            self.cancelled = true;
+            return Err(io::Error::other("cancelled"));
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self) -> Result<(), AnyError> {
+        self.progress.copied_files += 1;
+        Ok(self.report()?)
+    }
+}
+
+// reader of archived files, reports read bytes
+struct ProgressReader<'a, 'b> {
+    file: File,
+    progress: &'a mut ArchiveProgress<'b>,
+}
+
+impl Read for ProgressReader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.file.read(buf)?;
+        if size > 0 {
+            self.progress.progress.copied_bytes += size as u64;
+            self.progress.report()?;
+        }
+        Ok(size)
+    }
+}
+
+// walk items recursively, symlinks and other special files are skipped like ListDir does
+fn walk_items<F>(
+    items: &[ArchiveItem],
+    skip: Option<&Path>,
+    add_entry: &mut F,
+) -> Result<(), AnyError>
+where
+    F: FnMut(&Path, &str, &std::fs::Metadata) -> Result<(), AnyError>,
+{
+    for item in items {
+        if skip.is_some_and(|skip| skip == item.path) {
+            continue;
+        }
+        let metadata = item.path.symlink_metadata()?;
+        if metadata.is_file() {
+            add_entry(&item.path, &item.name, &metadata)?;
+        } else if metadata.is_dir() {
+            if !item.name.is_empty() {
+                add_entry(&item.path, &item.name, &metadata)?;
+            }
+            let mut children = vec![];
+            for entry in std::fs::read_dir(&item.path)? {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                // trash is hidden as in listing
+                if file_name == TRASH_DIR_NAME {
+                    continue;
+                }
+                let name = match item.name.is_empty() {
+                    true => file_name,
+                    false => format!("{}/{}", item.name, file_name),
+                };
+                children.push(ArchiveItem::new(entry.path(), &name));
+            }
+            children.sort_by(|a, b| a.name.cmp(&b.name));
+            walk_items(&children, skip, add_entry)?;
+        } else {
+            trace!("walk_items(), skip {:?}", &item.path);
+        }
+    }
+    Ok(())
+}
+
+fn write_tar<W: Write>(
+    items: &[ArchiveItem],
+    writer: W,
+    progress: &mut ArchiveProgress,
+) -> Result<W, AnyError> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    let skip = progress.skip.clone();
+    walk_items(items, skip.as_deref(), &mut |path, name, metadata| {
+        match metadata.is_dir() {
+            true => builder.append_dir(name, path)?,
+            false => {
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(metadata);
+                let file = File::open(path)?;
+                let reader = ProgressReader { file, progress };
+                builder.append_data(&mut header, name, reader)?;
+                progress.add_file()?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(builder.into_inner()?)
+}
+
+fn get_zip_options(metadata: &std::fs::Metadata) -> SimpleFileOptions {
+    let mut options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(metadata.len() >= u32::MAX as u64);
+    if let Some(mtime) = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| zip::DateTime::try_from(time::OffsetDateTime::from(mtime)).ok())
+    {
+        options = options.last_modified_time(mtime);
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        options = options.unix_permissions(metadata.permissions().mode());
+    }
+    options
+}
+
+fn write_zip<W: Write>(
+    items: &[ArchiveItem],
+    writer: W,
+    progress: &mut ArchiveProgress,
+) -> Result<W, AnyError> {
+    // stream writer doesn't need seeking, sizes are written after data
+    let mut zip = ZipWriter::new_stream(writer);
+    let skip = progress.skip.clone();
+    walk_items(items, skip.as_deref(), &mut |path, name, metadata| {
+        let options = get_zip_options(metadata);
+        match metadata.is_dir() {
+            // add_directory() skips the data descriptor required in stream mode, so add it as
+            // an empty stored file with name ending with '/'
+            true => {
+                let options = options.compression_method(CompressionMethod::Stored);
+                zip.start_file(format!("{}/", name), options)?;
+            }
+            false => {
+                zip.start_file(name, options)?;
+                let file = File::open(path)?;
+                io::copy(&mut ProgressReader { file, progress }, &mut zip)?;
+                progress.add_file()?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(zip.finish()?.into_inner())
+}
+
+fn write_archive<W: Write>(
+    format: ArchiveFormat,
+    items: &[ArchiveItem],
+    writer: W,
+    progress: &mut ArchiveProgress,
+) -> Result<W, AnyError> {
+    let mut writer = match format {
+        ArchiveFormat::Zip => write_zip(items, writer, progress)?,
+        ArchiveFormat::Tar => write_tar(items, writer, progress)?,
+        ArchiveFormat::TarGz => write_tar(
+            items,
+            GzEncoder::new(writer, Compression::default()),
+            progress,
+        )?
+        .finish()?,
+    };
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// Save archive of items to a new file, files and bytes are counted first for progress
+pub fn save_archive(
+    format: ArchiveFormat,
+    items: &[ArchiveItem],
+    target: &Path,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    let mut progress = ArchiveProgress::new(Some(target.to_path_buf()), on_progress);
+    walk_items(items, Some(target), &mut |_, _, metadata| {
+        if metadata.is_file() {
+            progress.progress.total_files += 1;
+            progress.progress.total_bytes += metadata.len();
+        }
+        Ok(())
+    })?;
+    trace!(
+        "save_archive(), target={:?}, total_files={}, total_bytes={}",
+        target,
+        progress.progress.total_files,
+        progress.progress.total_bytes
+    );
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+    let result = write_archive(format, items, BufWriter::new(file), &mut progress)
+        .and_then(|writer| Ok(writer.into_inner()?.sync_all()?));
+    match result {
+        Err(_) if progress.cancelled => Err(get_cancelled_error()),
+        result => result,
+    }
+}
+
+/// Build archive of items in the pool, and get the data as a stream
+pub fn get_archive_stream(
+    format: ArchiveFormat,
+    items: Vec<ArchiveItem>,
+    pool: FsPool,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (sender, receiver) = channel(ARCHIVE_CHANNEL_SIZE);
+    let archive = move || {
+        let error_sender = sender.clone();
+        let mut on_progress = |_: &CopyProgress| true;
+        let mut progress = ArchiveProgress::new(None, &mut on_progress);
+        let writer = ChannelWriter::new(sender);
+        if let Err(e) = write_archive(format, &items, writer, &mut progress) {
+            debug!(
+                "get_archive_stream(), failed to write archive, error={:?}",
+                e
+            );
+            // pass the error to body so the response is aborted instead of truncated silently
+            let error = io::Error::other(e.to_string());
+            let _ = error_sender.blocking_send(Err(error));
+        }
+    };
+    tokio::spawn(async move {
+        pool.run(move || {
+            archive();
+            Ok(())
+        })
+        .await
+    });
+    futures::stream::unfold(
+        receiver,
+        |mut receiver: Receiver<io::Result<Bytes>>| async {
+            receiver.recv().await.map(|data| (data, receiver))
+        },
+    )
+}
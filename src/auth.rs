@@ -0,0 +1,374 @@
+use crate::{
+    data::ServerInfo,
+    multiplex::is_grpc_request,
+    user::{UserPermission, UserProfile, ANONYMOUS_USER_NAME, DEFAULT_USER_NAME},
+};
+use axum::{
+    body::BoxBody,
+    response::{AppendHeaders, IntoResponse},
+};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use http::{
+    header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, SET_COOKIE, WWW_AUTHENTICATE},
+    HeaderValue, Method, StatusCode,
+};
+use hyper::{Body, Request, Response};
+use log::{debug, trace};
+use percent_encoding::percent_decode_str;
+use sha2::Sha256;
+use std::{
+    convert::Infallible,
+    path::Path,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tonic::Code;
+use tower::Service;
+
+const AUTH_COOKIE_NAME: &str = "serva_session";
+const AUTH_QUERY_NAME: &str = "token";
+// sessions expire after this, and all of them are revoked when the server restarts
+const SESSION_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+const SESSION_KEY_SIZE: usize = 32;
+
+enum AuthResult {
+    Allowed(Arc<UserProfile>),
+    // allowed by credentials in request, so session cookie should be set
+    AllowedByCredentials(Arc<UserProfile>, String),
+    Denied,
+}
+
+#[derive(Debug, Clone)]
+struct AuthEntry {
+    basic: Option<String>,
+    token: Option<String>,
+    profile: Arc<UserProfile>,
+}
+
+impl AuthEntry {
+    fn new(basic: Option<String>, token: Option<String>, profile: Arc<UserProfile>) -> Self {
+        AuthEntry {
+            basic,
+            token,
+            profile,
+        }
+    }
+}
+
+fn get_now_in_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+// random key of this process, so sessions could not be forged or derived from credentials
+fn generate_session_key() -> [u8; SESSION_KEY_SIZE] {
+    let mut key = [0; SESSION_KEY_SIZE];
+    getrandom::getrandom(&mut key).expect("Failed to generate session key");
+    key
+}
+
+#[derive(Debug, Clone)]
+pub struct Auth {
+    entries: Vec<AuthEntry>,
+    default_profile: Arc<UserProfile>,
+    anonymous_profile: Arc<UserProfile>,
+    has_basic: bool,
+    public_prefix: Option<String>,
+    session_key: [u8; SESSION_KEY_SIZE],
+    secure_cookie: bool,
+}
+
+impl From<&ServerInfo> for Auth {
+    fn from(server_info: &ServerInfo) -> Self {
+        let default_profile = Arc::new(UserProfile::new(
+            DEFAULT_USER_NAME,
+            &server_info.root,
+            server_info.get_default_permission(),
+        ));
+        let anonymous_profile = Arc::new(UserProfile::new(
+            ANONYMOUS_USER_NAME,
+            &server_info.root,
+            UserPermission::default(),
+        ));
+        // credentials from args are for the default profile
+        let mut entries = vec![];
+        if server_info.arg_auth.is_some() || server_info.arg_token.is_some() {
+            entries.push(AuthEntry::new(
+                server_info.arg_auth.clone(),
+                server_info.arg_token.clone(),
+                default_profile.clone(),
+            ));
+        }
+        for user in &server_info.users {
+            let basic = user
+                .password
+                .as_ref()
+                .map(|password| format!("{}:{}", user.profile.name, password));
+            let profile = Arc::new(user.profile.clone());
+            entries.push(AuthEntry::new(basic, user.token.clone(), profile));
+        }
+        let has_basic = entries.iter().any(|entry| entry.basic.is_some());
+        Auth {
+            entries,
+            default_profile,
+            anonymous_profile,
+            has_basic,
+            public_prefix: Some(server_info.prefix.clone()),
+            session_key: generate_session_key(),
+            secure_cookie: false,
+        }
+    }
+}
+
+impl Auth {
+    /// Require auth for all paths, otherwise embedded webapp is public so login could be shown
+    pub fn protect_all(mut self) -> Self {
+        self.public_prefix = None;
+        self
+    }
+
+    /// Send session cookie over https only
+    pub fn secure_cookie(mut self, secure: bool) -> Self {
+        self.secure_cookie = secure;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    fn is_public<B>(&self, req: &Request<B>) -> bool {
+        // preflight requests never carry credentials
+        if req.method() == Method::OPTIONS {
+            return true;
+        }
+        // only embedded files of webapp are public, which are only read
+        match &self.public_prefix {
+            Some(prefix) => {
+                !is_grpc_request(req)
+                    && matches!(*req.method(), Method::GET | Method::HEAD)
+                    && !is_prefixed_path(req.uri().path(), prefix)
+            }
+            None => false,
+        }
+    }
+
+    // index of entry is returned, so session cookie could be built for it
+    fn find_by_authorization(&self, value: &str) -> Option<usize> {
+        let (scheme, credentials) = value.split_once(' ')?;
+        let credentials = credentials.trim();
+        match scheme.to_ascii_lowercase().as_str() {
+            "basic" => {
+                let decoded = base64::decode(credentials).ok()?;
+                self.entries.iter().position(|entry| {
+                    matches!(&entry.basic, Some(basic) if is_same_secret(&decoded, basic.as_bytes()))
+                })
+            }
+            "bearer" => self.find_by_token(credentials),
+            _ => None,
+        }
+    }
+
+    fn find_by_token(&self, value: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| {
+            matches!(&entry.token, Some(token) if is_same_secret(value.as_bytes(), token.as_bytes()))
+        })
+    }
+
+    fn find_by_query(&self, query: Option<&str>) -> Option<usize> {
+        serde_urlencoded::from_str::<Vec<(String, String)>>(query?)
+            .unwrap_or_default()
+            .iter()
+            .filter(|(name, _)| name == AUTH_QUERY_NAME)
+            .find_map(|(_, value)| self.find_by_token(value))
+    }
+
+    // signature of session, entry is identified by its index in this process
+    fn get_session_signature(&self, index: usize, expires: u64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.session_key)
+            .expect("HMAC accepts keys of any size");
+        let entry = &self.entries[index];
+        mac.update(format!("{}\n{}\n{}", index, entry.profile.name, expires).as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    // session is "<index>.<expires>.<signature>", valid until expires in seconds
+    fn build_session_cookie(&self, index: usize) -> String {
+        let expires = get_now_in_secs() + SESSION_LIFETIME.as_secs();
+        let signature = self.get_session_signature(index, expires);
+        format!(
+            "{}={}.{}.{}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict{}",
+            AUTH_COOKIE_NAME,
+            index,
+            expires,
+            signature,
+            SESSION_LIFETIME.as_secs(),
+            match self.secure_cookie {
+                true => "; Secure",
+                false => "",
+            }
+        )
+    }
+
+    fn find_by_session(&self, value: &str) -> Option<&AuthEntry> {
+        let mut parts = value.splitn(3, '.');
+        let index = parts.next()?.parse::<usize>().ok()?;
+        let expires = parts.next()?.parse::<u64>().ok()?;
+        let signature = parts.next()?;
+        if index >= self.entries.len() || expires <= get_now_in_secs() {
+            return None;
+        }
+        let expected = self.get_session_signature(index, expires);
+        match is_same_secret(signature.as_bytes(), expected.as_bytes()) {
+            true => self.entries.get(index),
+            false => None,
+        }
+    }
+
+    fn find_by_cookie(&self, value: &str) -> Option<&AuthEntry> {
+        value
+            .split(';')
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .filter(|(name, _)| *name == AUTH_COOKIE_NAME)
+            .find_map(|(_, value)| self.find_by_session(value))
+    }
+
+    fn check<B>(&self, req: &Request<B>) -> AuthResult {
+        if !self.is_enabled() {
+            return AuthResult::Allowed(self.default_profile.clone());
+        }
+        if self.is_public(req) {
+            return AuthResult::Allowed(self.anonymous_profile.clone());
+        }
+        let headers = req.headers();
+        let entry = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(|value| self.find_by_cookie(value));
+        if let Some(entry) = entry {
+            return AuthResult::Allowed(entry.profile.clone());
+        }
+        let index = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| self.find_by_authorization(value))
+            .or_else(|| self.find_by_query(req.uri().query()));
+        match index {
+            Some(index) => AuthResult::AllowedByCredentials(
+                self.entries[index].profile.clone(),
+                self.build_session_cookie(index),
+            ),
+            None => AuthResult::Denied,
+        }
+    }
+
+    fn build_unauthorized_response<B>(&self, req: &Request<B>) -> Response<BoxBody> {
+        // grpc clients expect the status in headers of a trailers-only response
+        if is_grpc_request(req) {
+            let content_type = req
+                .headers()
+                .get(CONTENT_TYPE)
+                .cloned()
+                .unwrap_or_else(|| HeaderValue::from_static("application/grpc"));
+            let headers = AppendHeaders([
+                ("grpc-status", (Code::Unauthenticated as i32).to_string()),
+                ("grpc-message", "Unauthenticated".to_string()),
+            ]);
+            return (StatusCode::OK, [(CONTENT_TYPE, content_type)], headers).into_response();
+        }
+        match self.has_basic {
+            true => {
+                let headers = [(WWW_AUTHENTICATE, "Basic realm=\"serva\"")];
+                (StatusCode::UNAUTHORIZED, headers).into_response()
+            }
+            false => StatusCode::UNAUTHORIZED.into_response(),
+        }
+    }
+}
+
+// path is decoded and normalized as file handlers do, so encoded chars or repeated slashes
+// never make a path of fs files look like a public one
+fn is_prefixed_path(path: &str, prefix: &str) -> bool {
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    Path::new(path.as_ref()).strip_prefix(prefix).is_ok()
+}
+
+fn is_same_secret(left: &[u8], right: &[u8]) -> bool {
+    // compare in constant time to avoid leaking secret by timing
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0, |acc, (left, right)| acc | (left ^ right))
+            == 0
+}
+
+pub struct AuthService<S> {
+    inner: S,
+    auth: Arc<Auth>,
+}
+
+impl<S> AuthService<S> {
+    pub fn new(inner: S, auth: Auth) -> Self {
+        Self {
+            inner,
+            auth: Arc::new(auth),
+        }
+    }
+}
+
+impl<S> Clone for AuthService<S>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            auth: self.auth.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for AuthService<S>
+where
+    S: Service<Request<Body>, Error = Infallible>,
+    S::Response: IntoResponse,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        match self.auth.check(&req) {
+            AuthResult::Allowed(profile) => {
+                req.extensions_mut().insert(profile);
+                let future = self.inner.call(req);
+                Box::pin(async move { Ok(future.await?.into_response()) })
+            }
+            AuthResult::AllowedByCredentials(profile, cookie) => {
+                trace!("AuthService::call(), user={}, set cookie", profile.name);
+                req.extensions_mut().insert(profile);
+                let future = self.inner.call(req);
+                Box::pin(async move {
+                    let res = future.await?;
+                    Ok(([(SET_COOKIE, cookie)], res).into_response())
+                })
+            }
+            AuthResult::Denied => {
+                debug!("AuthService::call(), denied, uri={}", req.uri());
+                let response = self.auth.build_unauthorized_response(&req);
+                Box::pin(async move { Ok(response) })
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use http::{
+    header::{
+        HeaderName, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+        IF_UNMODIFIED_SINCE, LAST_MODIFIED, RANGE,
+    },
+    HeaderMap, HeaderValue,
+};
+use log::trace;
+use std::{
+    fs::Metadata,
+    time::{Duration, SystemTime},
+};
+
+/// Result of evaluating conditional headers, refer to RFC 9110 section 13.2.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    Passed,
+    NotModified,
+    Failed,
+}
+
+/// Validators of a file, used for conditional requests
+#[derive(Debug, Clone)]
+pub struct Validator {
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validator {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        #[cfg(not(target_os = "windows"))]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(target_os = "windows")]
+        let inode = 0;
+        Validator {
+            etag: format!(
+                "\"{:x}-{:x}-{:x}\"",
+                metadata.len(),
+                mtime.as_nanos(),
+                inode
+            ),
+            last_modified: metadata.modified().ok(),
+        }
+    }
+
+    pub fn from_embedded(metadata: &rust_embed::Metadata) -> Self {
+        let hash: String = metadata.sha256_hash()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Validator {
+            etag: format!("\"{}\"", hash),
+            last_modified: metadata
+                .last_modified()
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn get_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Some(last_modified) = self.last_modified {
+            let last_modified = httpdate::fmt_http_date(last_modified);
+            if let Ok(last_modified) = HeaderValue::from_str(&last_modified) {
+                headers.insert(LAST_MODIFIED, last_modified);
+            }
+        }
+        headers
+    }
+
+    fn is_modified_since(&self, date: SystemTime) -> bool {
+        match self.last_modified {
+            Some(last_modified) => get_secs(last_modified) > get_secs(date),
+            None => true,
+        }
+    }
+
+    fn is_etag_in_list(&self, list: &str, weak: bool) -> bool {
+        list.split(',').map(|etag| etag.trim()).any(|etag| {
+            etag == "*"
+                || match etag.strip_prefix("W/") {
+                    Some(weak_etag) => weak && weak_etag == self.etag,
+                    None => etag == self.etag,
+                }
+        })
+    }
+}
+
+// http date is in seconds, so times are compared in seconds
+fn get_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn get_header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn get_header_date(headers: &HeaderMap, name: &HeaderName) -> Option<SystemTime> {
+    get_header_str(headers, name).and_then(|value| httpdate::parse_http_date(value).ok())
+}
+
+/// Evaluate If-Match, If-Unmodified-Since, If-None-Match and If-Modified-Since of GET requests
+pub fn evaluate_preconditions(headers: &HeaderMap, validator: &Validator) -> Precondition {
+    let result = if let Some(if_match) = get_header_str(headers, &IF_MATCH) {
+        match validator.is_etag_in_list(if_match, false) {
+            true => Precondition::Passed,
+            false => Precondition::Failed,
+        }
+    } else {
+        match get_header_date(headers, &IF_UNMODIFIED_SINCE) {
+            Some(date) if validator.is_modified_since(date) => Precondition::Failed,
+            _ => Precondition::Passed,
+        }
+    };
+    if result == Precondition::Failed {
+        trace!("evaluate_preconditions(), failed");
+        return result;
+    }
+    // If-Modified-Since is ignored when If-None-Match exists
+    let not_modified = match get_header_str(headers, &IF_NONE_MATCH) {
+        Some(if_none_match) => validator.is_etag_in_list(if_none_match, true),
+        None => matches!(
+            get_header_date(headers, &IF_MODIFIED_SINCE),
+            Some(date) if !validator.is_modified_since(date)
+        ),
+    };
+    trace!("evaluate_preconditions(), not_modified={}", not_modified);
+    match not_modified {
+        true => Precondition::NotModified,
+        false => Precondition::Passed,
+    }
+}
+
+/// Range is ignored when If-Range doesn't match, so the whole changed file is sent
+pub fn is_range_allowed(headers: &HeaderMap, validator: &Validator) -> bool {
+    if !headers.contains_key(RANGE) {
+        return false;
+    }
+    let if_range = match get_header_str(headers, &IF_RANGE) {
+        Some(if_range) => if_range.trim(),
+        None => return true,
+    };
+    // weak etags never match, and dates must match exactly
+    if if_range.starts_with('"') {
+        return if_range == validator.etag;
+    }
+    match (httpdate::parse_http_date(if_range), validator.last_modified) {
+        (Ok(date), Some(last_modified)) => get_secs(last_modified) == get_secs(date),
+        _ => false,
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::anyhow;
+use log::trace;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+type AnyError = anyhow::Error;
+
+// names tried before giving up, e.g. "a (1).txt" to "a (9999).txt"
+const MAX_RENAME_SUFFIX: u32 = 9999;
+// random temp names tried before giving up, they hardly ever exist already
+const MAX_TEMP_NAME_ATTEMPTS: u32 = 16;
+
+/// What to do when the target of copying, moving, renaming or uploading already exists,
+/// it fails by default over both HTTP and gRPC, overwriting must be asked for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    #[default]
+    Fail,
+    Overwrite,
+    Rename,
+    Skip,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = AnyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "skip" => Ok(ConflictPolicy::Skip),
+            _ => Err(anyhow!("unknown conflict policy {}", s)),
+        }
+    }
+}
+
+// symlinks are entries themselves, so dangling ones exist too
+fn is_existing(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+/// Get name with suffix like "a (1).txt", the extension is kept for files
+fn get_suffixed_name(path: &Path, index: u32) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) if !path.is_dir() => {
+            format!("{} ({}).{}", stem, index, extension.to_string_lossy())
+        }
+        _ => format!(
+            "{} ({})",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            index
+        ),
+    }
+}
+
+/// Get path of temp entry beside target with a random part, like "a.txt.1f2e3d4c5b6a7988.copying",
+/// it doesn't exist, so it never collides with entries of users and is created by the caller only
+pub fn get_temp_path(target: &Path, suffix: &str) -> Result<PathBuf, AnyError> {
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| anyhow!("no file name"))?
+        .to_string_lossy();
+    for _ in 0..MAX_TEMP_NAME_ATTEMPTS {
+        let mut random = [0; 8];
+        getrandom::getrandom(&mut random)?;
+        let temp = target.with_file_name(format!(
+            "{}.{:016x}.{}",
+            file_name,
+            u64::from_le_bytes(random),
+            suffix
+        ));
+        if !is_existing(&temp) {
+            return Ok(temp);
+        }
+    }
+    Err(anyhow!("no available temp name for {:?}", target))
+}
+
+/// Resolve conflict of target by policy, None means the operation should be skipped.
+/// Existing target is never removed here, overwriting is done by the operation itself.
+pub fn resolve_conflict(
+    target: &Path,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>, AnyError> {
+    if !is_existing(target) {
+        return Ok(Some(target.to_path_buf()));
+    }
+    trace!(
+        "resolve_conflict(), target={:?}, policy={:?}",
+        target,
+        policy
+    );
+    match policy {
+        ConflictPolicy::Fail => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists",
+                target.file_name().unwrap_or_default().to_string_lossy()
+            ),
+        )
+        .into()),
+        ConflictPolicy::Overwrite => Ok(Some(target.to_path_buf())),
+        ConflictPolicy::Rename => (1..=MAX_RENAME_SUFFIX)
+            .map(|index| target.with_file_name(get_suffixed_name(target, index)))
+            .find(|path| !is_existing(path))
+            .map(Some)
+            .ok_or_else(|| anyhow!("no available name for {:?}", target)),
+        ConflictPolicy::Skip => Ok(None),
+    }
+}
+
+/// Remove existing entry before overwriting it, symlinks are removed themselves
+pub fn remove_existing(target: &Path) -> Result<(), AnyError> {
+    match target.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(target)?,
+        Ok(_) => std::fs::remove_file(target)?,
+        Err(_) => return Ok(()),
+    }
+    trace!("remove_existing(), target={:?}", target);
+    Ok(())
+}
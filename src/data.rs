@@ -1,8 +1,16 @@
+use crate::{
+    pool::FsPool,
+    resolve::{PathError, SymlinkPolicy},
+    upload::HashMismatchError,
+    user::{load_users, User, UserPermission},
+};
 use anyhow::anyhow;
+use http::StatusCode;
 use std::{
     fmt::{Display, Formatter},
+    io,
     net::{IpAddr, Ipv4Addr},
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
 
 type AnyError = anyhow::Error;
@@ -45,13 +53,29 @@ pub struct ServerInfo {
     pub arg_allow_download: bool,
     pub arg_ip: IpAddr,
     pub arg_port: u16,
+    pub arg_auth: Option<String>,
+    pub arg_token: Option<String>,
+    pub arg_users: Option<String>,
+    pub arg_inline_types: Vec<String>,
+    pub arg_enable_index: bool,
+    pub arg_symlinks: SymlinkPolicy,
+    pub arg_trash_retention_days: u64,
+    pub arg_fs_workers: usize,
+    pub arg_io_workers: usize,
+    pub arg_stream_workers: usize,
     pub root: PathBuf,
     pub root_canonical: String,
     pub prefix: String,
     pub available_ip: Vec<IpAddr>,
+    pub users: Vec<User>,
+    // pools are shared by http and grpc, so filesystem work is bounded for both
+    pub io_pool: FsPool,
+    pub stream_pool: FsPool,
+    pub manage_pool: FsPool,
 }
 
 impl ServerInfo {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         path: &str,
         allow_cors: bool,
@@ -60,7 +84,23 @@ impl ServerInfo {
         allow_download: bool,
         ip: IpAddr,
         port: u16,
+        auth: Option<String>,
+        token: Option<String>,
+        users: Option<String>,
+        inline_types: Option<String>,
+        enable_index: bool,
+        symlinks: SymlinkPolicy,
+        trash_retention_days: u64,
+        fs_workers: usize,
+        io_workers: usize,
+        stream_workers: usize,
     ) -> Result<Self, AnyError> {
+        if matches!(&auth, Some(auth) if !auth.contains(':')) {
+            return Err(anyhow!("auth should be in format user:password"));
+        }
+        if matches!(&token, Some(token) if token.is_empty()) {
+            return Err(anyhow!("token should not be empty"));
+        }
         let root = PathBuf::from(path).canonicalize()?;
         let root_canonical = root
             .to_str()
@@ -68,6 +108,10 @@ impl ServerInfo {
             .to_string();
         let prefix = format!("/{}/", generate_prefix()?);
         let available_ip = get_available_ip(ip)?;
+        let user_list = match &users {
+            Some(users) => load_users(users, &root, symlinks)?,
+            None => vec![],
+        };
         Ok(ServerInfo {
             arg_path: path.to_string(),
             arg_allow_cors: allow_cors,
@@ -76,12 +120,49 @@ impl ServerInfo {
             arg_allow_download: allow_download,
             arg_ip: ip,
             arg_port: port,
+            arg_auth: auth,
+            arg_token: token,
+            arg_users: users,
+            arg_inline_types: inline_types
+                .unwrap_or_default()
+                .split(',')
+                .map(|inline_type| inline_type.trim().to_ascii_lowercase())
+                .filter(|inline_type| !inline_type.is_empty())
+                .collect(),
+            arg_enable_index: enable_index,
+            arg_symlinks: symlinks,
+            arg_trash_retention_days: trash_retention_days,
+            arg_fs_workers: fs_workers,
+            arg_io_workers: io_workers,
+            arg_stream_workers: stream_workers,
             root,
             root_canonical,
             prefix,
             available_ip,
+            users: user_list,
+            io_pool: FsPool::new("io", io_workers),
+            stream_pool: FsPool::new("stream", stream_workers),
+            manage_pool: FsPool::new("manage", fs_workers),
         })
     }
+
+    /// Whether clients are authenticated, otherwise they are all the default user
+    pub fn is_auth_enabled(&self) -> bool {
+        self.arg_auth.is_some() || self.arg_token.is_some() || !self.users.is_empty()
+    }
+
+    /// Permission from args, used when auth is disabled or by --auth and --token
+    pub fn get_default_permission(&self) -> UserPermission {
+        UserPermission {
+            create: self.arg_allow_manage,
+            copy: self.arg_allow_manage,
+            r#move: self.arg_allow_manage,
+            delete: self.arg_allow_manage,
+            rename: self.arg_allow_manage,
+            upload: self.arg_allow_upload,
+            download: self.arg_allow_download,
+        }
+    }
 }
 
 impl Display for ServerInfo {
@@ -99,6 +180,27 @@ impl Display for ServerInfo {
             self.arg_allow_upload,
             self.arg_allow_download
         )?;
+        writeln!(
+            f,
+            "    basic_auth:{}; token_auth:{}; users:{:?}({})",
+            self.arg_auth.is_some(),
+            self.arg_token.is_some(),
+            self.arg_users,
+            self.users.len()
+        )?;
+        writeln!(
+            f,
+            "    inline_types:{:?}; enable_index:{}; symlinks:{:?}; trash_retention_days:{}",
+            self.arg_inline_types,
+            self.arg_enable_index,
+            self.arg_symlinks,
+            self.arg_trash_retention_days
+        )?;
+        writeln!(
+            f,
+            "    fs_workers:{}; io_workers:{}; stream_workers:{}",
+            self.arg_fs_workers, self.arg_io_workers, self.arg_stream_workers
+        )?;
         write!(
             f,
             "    root:{}; prefix:{}",
@@ -108,13 +210,122 @@ impl Display for ServerInfo {
     }
 }
 
-pub fn get_valid_joined_path<P>(base: &Path, suffix: P) -> Result<PathBuf, AnyError>
-where
-    P: AsRef<Path>,
-{
-    let joined_path = base.join(suffix).canonicalize()?;
-    if !joined_path.starts_with(base) {
-        return Err(anyhow!("accessing parent directory is forbidden"));
+/// Errors shared by http and grpc layers, so clients get proper status codes
+#[derive(Debug)]
+pub enum ServaError {
+    NotFound(String),
+    AlreadyExists(String),
+    PermissionDenied(String),
+    InvalidArgument(String),
+    DataLoss(String),
+    Internal(String),
+}
+
+impl ServaError {
+    fn from_io_kind(kind: io::ErrorKind, message: String) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => ServaError::NotFound(message),
+            io::ErrorKind::AlreadyExists | io::ErrorKind::DirectoryNotEmpty => {
+                ServaError::AlreadyExists(message)
+            }
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                ServaError::PermissionDenied(message)
+            }
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidFilename
+            | io::ErrorKind::NotADirectory
+            | io::ErrorKind::IsADirectory
+            | io::ErrorKind::Unsupported => ServaError::InvalidArgument(message),
+            _ => ServaError::Internal(message),
+        }
+    }
+
+    fn from_path_error(e: &PathError, message: String) -> Self {
+        match e {
+            PathError::NotFound(_) => ServaError::NotFound(message),
+            PathError::SymlinkDenied(_) | PathError::InTrash(_) => {
+                ServaError::PermissionDenied(message)
+            }
+            PathError::Absolute(_)
+            | PathError::ParentDir(_)
+            | PathError::InvalidName(_)
+            | PathError::NotDir(_)
+            | PathError::OutsideRoot(_) => ServaError::InvalidArgument(message),
+            PathError::Io(_, e) => ServaError::from_io_kind(e.kind(), message),
+        }
+    }
+
+    fn from_fs_extra_error(e: &fs_extra::error::Error, message: String) -> Self {
+        use fs_extra::error::ErrorKind;
+        match &e.kind {
+            ErrorKind::NotFound => ServaError::NotFound(message),
+            ErrorKind::AlreadyExists => ServaError::AlreadyExists(message),
+            ErrorKind::PermissionDenied => ServaError::PermissionDenied(message),
+            ErrorKind::InvalidFolder
+            | ErrorKind::InvalidFile
+            | ErrorKind::InvalidFileName
+            | ErrorKind::InvalidPath => ServaError::InvalidArgument(message),
+            ErrorKind::Io(e) => ServaError::from_io_kind(e.kind(), message),
+            _ => ServaError::Internal(message),
+        }
+    }
+
+    pub fn get_status_code(&self) -> StatusCode {
+        match self {
+            ServaError::NotFound(_) => StatusCode::NOT_FOUND,
+            ServaError::AlreadyExists(_) => StatusCode::CONFLICT,
+            ServaError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            ServaError::InvalidArgument(_) => StatusCode::BAD_REQUEST,
+            // request was well formed, but its content doesn't match its hash
+            ServaError::DataLoss(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServaError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn get_code(&self) -> tonic::Code {
+        match self {
+            ServaError::NotFound(_) => tonic::Code::NotFound,
+            ServaError::AlreadyExists(_) => tonic::Code::AlreadyExists,
+            ServaError::PermissionDenied(_) => tonic::Code::PermissionDenied,
+            ServaError::InvalidArgument(_) => tonic::Code::InvalidArgument,
+            ServaError::DataLoss(_) => tonic::Code::DataLoss,
+            ServaError::Internal(_) => tonic::Code::Internal,
+        }
+    }
+
+    pub fn get_message(&self) -> &str {
+        match self {
+            ServaError::NotFound(message)
+            | ServaError::AlreadyExists(message)
+            | ServaError::PermissionDenied(message)
+            | ServaError::InvalidArgument(message)
+            | ServaError::DataLoss(message)
+            | ServaError::Internal(message) => message,
+        }
+    }
+}
+
+// the first known error in the chain decides the kind, the message is of the whole error
+impl From<AnyError> for ServaError {
+    fn from(e: AnyError) -> Self {
+        let message = e.to_string();
+        for cause in e.chain() {
+            if let Some(e) = cause.downcast_ref::<PathError>() {
+                return ServaError::from_path_error(e, message);
+            } else if let Some(e) = cause.downcast_ref::<io::Error>() {
+                return ServaError::from_io_kind(e.kind(), message);
+            } else if let Some(e) = cause.downcast_ref::<fs_extra::error::Error>() {
+                return ServaError::from_fs_extra_error(e, message);
+            } else if cause.is::<HashMismatchError>() {
+                return ServaError::DataLoss(message);
+            }
+        }
+        ServaError::Internal(message)
+    }
+}
+
+impl From<ServaError> for tonic::Status {
+    fn from(e: ServaError) -> Self {
+        tonic::Status::new(e.get_code(), e.get_message())
     }
-    Ok(joined_path)
 }
@@ -1,84 +1,350 @@
-use crate::data::{get_valid_joined_path, ServerInfo};
+use crate::{
+    archive::ArchiveFormat,
+    conflict::ConflictPolicy,
+    data::{ServaError, ServerInfo},
+    index::{get_matched_lines, ContentIndex},
+    job::{Job, JobInfo, JobRegistry, JobResult, JobState},
+    listing::{is_hidden, list_dir_page, ListOptions, SortBy, SortKey},
+    manage::{
+        copy_file, create_archive, create_dir, delete_file, move_file, rename_file, CopyProgress,
+        ManageOptions, ProgressHandler,
+    },
+    pool::FsPool,
+    resolve::{resolve_path, PathError, SymlinkPolicy},
+    search::{glob_to_regex, search_files, SearchFilter},
+    thumbnail::has_thumbnail,
+    trash::{is_in_trash, start_purging, Trash, TrashItem},
+    upload::{
+        get_sha256_of_data, get_upload_path_names, verify_hash, write_chunk, UploadFileWriter,
+        UploadTarget,
+    },
+    user::{UserPermission, UserProfile},
+    watch::{watch_dir, Change, ChangeKind, WatcherLimit},
+};
 use anyhow::anyhow;
-use log::{debug, trace};
+use log::{debug, trace, warn};
 use proto::{
+    batch_manage_response, job, list_dir_request,
     manage_dir_or_file_request::Operation,
+    search_files_response,
     serva_manager_server::{ServaManager, ServaManagerServer},
-    Address, Directory, File, GetConfigRequest, GetConfigResponse, ListDirRequest, ListDirResponse,
-    ManageDirOrFileRequest, ManageDirOrFileResponse, Permission, UploadFileChunkRequest,
-    UploadFileChunkResponse,
+    submit_job_request, upload_file_request, watch_event, Address, ArchiveJob, BatchManageRequest,
+    BatchManageResponse, BatchProgress, BatchResult, ByteRange, CancelJobRequest,
+    CancelJobResponse, Directory, EmptyTrashRequest, EmptyTrashResponse, EntryKind, File,
+    GetConfigRequest, GetConfigResponse, GetJobRequest, GetJobResponse, LineMatch, ListDirRequest,
+    ListDirResponse, ListJobsRequest, ListJobsResponse, ListTrashRequest, ListTrashResponse,
+    ManageDirOrFileRequest, ManageDirOrFileResponse, Permission, QueryUploadStatusRequest,
+    QueryUploadStatusResponse, RestoreFromTrashRequest, RestoreFromTrashResponse,
+    SearchContentRequest, SearchContentResponse, SearchFilesRequest, SearchFilesResponse,
+    SubmitJobRequest, SubmitJobResponse, UploadFileChunkRequest, UploadFileChunkResponse,
+    UploadFileRequest, UploadFileResponse, WatchDirRequest, WatchDirResponse, WatchEvent,
+    WatchJobRequest, WatchJobResponse,
 };
-#[cfg(not(target_os = "windows"))]
-use std::os::unix::prelude::FileExt;
-#[cfg(target_os = "windows")]
-use std::os::windows::fs::FileExt;
+use regex::RegexBuilder;
 use std::{
+    collections::HashMap,
     fmt::Debug,
-    fs::{read_dir, remove_file},
+    fs::{FileType, Metadata},
+    io,
     net::IpAddr,
+    ops::Range,
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tonic::{Code, Request, Response, Status};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status, Streaming};
 
 type AnyError = anyhow::Error;
-type DirEntriesTuple = (Vec<Directory>, Vec<File>);
+
+const DEFAULT_SEARCH_RESULTS: u32 = 1000;
+const MAX_SEARCH_RESULTS: u32 = 10000;
+const SEARCH_CHANNEL_SIZE: usize = 64;
+const DEFAULT_PAGE_SIZE: u32 = 1000;
+const MAX_PAGE_SIZE: u32 = 10000;
+const WATCH_CHANNEL_SIZE: usize = 16;
+const BATCH_CHANNEL_SIZE: usize = 64;
+const BATCH_PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+const JOB_CHANNEL_SIZE: usize = 16;
+// upload sessions without chunks for this long are aborted, and their temp files removed
+const UPLOAD_SESSION_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+const UPLOAD_SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_MATCHED_LINES: u32 = 10;
+const MAX_MATCHED_LINES: u32 = 100;
 type TonicListDirReq = Request<ListDirRequest>;
 type TonicListDirResp = Response<ListDirResponse>;
 type TonicGetCfgReq = Request<GetConfigRequest>;
 type TonicGetCfgResp = Response<GetConfigResponse>;
 type TonicUploadFileChunkReq = Request<UploadFileChunkRequest>;
 type TonicUploadFileChunkResp = Response<UploadFileChunkResponse>;
+type TonicUploadFileReq = Request<Streaming<UploadFileRequest>>;
+type TonicUploadFileResp = Response<UploadFileResponse>;
+type TonicQueryUploadStatusReq = Request<QueryUploadStatusRequest>;
+type TonicQueryUploadStatusResp = Response<QueryUploadStatusResponse>;
 type TonicManageDirOrFileReq = Request<ManageDirOrFileRequest>;
 type TonicManageDirOrFileResp = Response<ManageDirOrFileResponse>;
+type TonicSearchFilesReq = Request<SearchFilesRequest>;
+type TonicSearchFilesResp = Response<ReceiverStream<Result<SearchFilesResponse, Status>>>;
+type TonicSearchContentReq = Request<SearchContentRequest>;
+type TonicSearchContentResp = Response<ReceiverStream<Result<SearchContentResponse, Status>>>;
+type TonicWatchDirReq = Request<WatchDirRequest>;
+type TonicWatchDirResp = Response<ReceiverStream<Result<WatchDirResponse, Status>>>;
+type TonicBatchManageReq = Request<BatchManageRequest>;
+type TonicBatchManageResp = Response<ReceiverStream<Result<BatchManageResponse, Status>>>;
+type TonicListTrashReq = Request<ListTrashRequest>;
+type TonicListTrashResp = Response<ListTrashResponse>;
+type TonicRestoreFromTrashReq = Request<RestoreFromTrashRequest>;
+type TonicRestoreFromTrashResp = Response<RestoreFromTrashResponse>;
+type TonicEmptyTrashReq = Request<EmptyTrashRequest>;
+type TonicEmptyTrashResp = Response<EmptyTrashResponse>;
+type TonicSubmitJobReq = Request<SubmitJobRequest>;
+type TonicSubmitJobResp = Response<SubmitJobResponse>;
+type TonicListJobsReq = Request<ListJobsRequest>;
+type TonicListJobsResp = Response<ListJobsResponse>;
+type TonicGetJobReq = Request<GetJobRequest>;
+type TonicGetJobResp = Response<GetJobResponse>;
+type TonicCancelJobReq = Request<CancelJobRequest>;
+type TonicCancelJobResp = Response<CancelJobResponse>;
+type TonicWatchJobReq = Request<WatchJobRequest>;
+type TonicWatchJobResp = Response<ReceiverStream<Result<WatchJobResponse, Status>>>;
 type ServaManagerServerImpl = ServaManagerServer<ServaManagerServiceImpl>;
 
+#[allow(clippy::all)]
 pub mod proto {
     include!("generated/api.rs");
 }
 
-const UPLOAD_FILE_SUFFIX: &str = "uploading";
-
 #[derive(Debug)]
 struct Config {
-    root_path: PathBuf,
     root_relative: String,
-    root_absolute: String,
     prefix: String,
     available_ip: Vec<IpAddr>,
     port: u16,
-    permission: Permission,
-    allow_upload: bool,
-    allow_manage: bool,
+    enable_index: bool,
+    symlinks: SymlinkPolicy,
+    root: PathBuf,
+    trash_retention: Option<Duration>,
+    auth_enabled: bool,
 }
 
 impl From<&ServerInfo> for Config {
     fn from(server_info: &ServerInfo) -> Self {
-        let permission = Permission {
-            create: server_info.arg_allow_manage,
-            copy: server_info.arg_allow_manage,
-            r#move: server_info.arg_allow_manage,
-            delete: server_info.arg_allow_manage,
-            rename: server_info.arg_allow_manage,
-            upload: server_info.arg_allow_upload,
-            download: server_info.arg_allow_download,
-        };
         Config {
-            root_path: server_info.root.clone(),
             root_relative: server_info.arg_path.clone(),
-            root_absolute: server_info.root_canonical.clone(),
             prefix: server_info.prefix.clone(),
             available_ip: server_info.available_ip.clone(),
             port: server_info.arg_port,
-            permission,
-            allow_upload: server_info.arg_allow_upload,
-            allow_manage: server_info.arg_allow_manage,
+            enable_index: server_info.arg_enable_index,
+            symlinks: server_info.arg_symlinks,
+            root: server_info.root.clone(),
+            trash_retention: match server_info.arg_trash_retention_days {
+                0 => None,
+                days => Some(Duration::from_secs(days * 24 * 60 * 60)),
+            },
+            auth_enabled: server_info.is_auth_enabled(),
+        }
+    }
+}
+
+impl Config {
+    // owner of limited resources like watchers and jobs, None when auth is disabled,
+    // since all clients are the same default user then and only global limits apply
+    fn get_limited_owner<'a>(&self, user: &'a UserProfile) -> Option<&'a str> {
+        match self.auth_enabled {
+            true => Some(&user.name),
+            false => None,
         }
     }
 }
 
+impl From<&UserPermission> for Permission {
+    fn from(permission: &UserPermission) -> Self {
+        Permission {
+            create: permission.create,
+            copy: permission.copy,
+            r#move: permission.r#move,
+            delete: permission.delete,
+            rename: permission.rename,
+            upload: permission.upload,
+            download: permission.download,
+        }
+    }
+}
+
+// profile of the caller is inserted into extensions by auth service
+macro_rules! get_user_or_return {
+    ($request: expr) => {
+        match $request.extensions().get::<Arc<UserProfile>>() {
+            Some(user) => user.clone(),
+            None => return Err(Status::new(Code::Unauthenticated, "Unknown user")),
+        }
+    };
+}
+
+#[derive(Debug)]
+struct UploadSession {
+    file_size: u64,
+    // target resolved by conflict policy when the first chunk arrives
+    upload: Option<UploadTarget>,
+    // received data as sorted, non-overlapping and non-adjacent ranges
+    received: Vec<Range<u64>>,
+    // abandoned sessions are expired by this
+    last_active: Instant,
+    // chunks being written without the lock, the session is busy until they are done
+    writing: usize,
+}
+
+impl UploadSession {
+    fn new(file_size: u64) -> Self {
+        UploadSession {
+            file_size,
+            upload: None,
+            received: vec![],
+            last_active: Instant::now(),
+            writing: 0,
+        }
+    }
+
+    fn add_received(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        let mut merged = range;
+        let mut received = Vec::with_capacity(self.received.len() + 1);
+        for current in self.received.drain(..) {
+            if current.end < merged.start || merged.end < current.start {
+                received.push(current);
+            } else {
+                merged = merged.start.min(current.start)..merged.end.max(current.end);
+            }
+        }
+        let index = received.partition_point(|r| r.start < merged.start);
+        received.insert(index, merged);
+        self.received = received;
+    }
+
+    fn get_received_size(&self) -> u64 {
+        self.received.iter().map(|r| r.end - r.start).sum()
+    }
+
+    fn get_missing(&self) -> Vec<Range<u64>> {
+        let mut missing = vec![];
+        let mut offset = 0;
+        for range in &self.received {
+            if range.start > offset {
+                missing.push(offset..range.start);
+            }
+            offset = range.end;
+        }
+        if offset < self.file_size {
+            missing.push(offset..self.file_size);
+        }
+        missing
+    }
+
+    fn is_complete(&self) -> bool {
+        self.get_received_size() == self.file_size
+    }
+}
+
+type SharedUploadSession = Arc<Mutex<UploadSession>>;
+
+// in-memory registry of uploads in progress, keyed by the target path name
+#[derive(Debug, Default)]
+struct UploadSessionRegistry {
+    sessions: Mutex<HashMap<PathBuf, SharedUploadSession>>,
+}
+
+impl UploadSessionRegistry {
+    fn get(&self, target: &Path) -> Option<SharedUploadSession> {
+        let sessions = self.sessions.lock().ok()?;
+        sessions.get(target).cloned()
+    }
+
+    fn get_or_create(
+        &self,
+        target: &Path,
+        file_size: u64,
+    ) -> Result<SharedUploadSession, AnyError> {
+        let mut sessions = self
+            .sessions
+            .lock()
+            .map_err(|_| anyhow!("upload session registry poisoned"))?;
+        let session = sessions
+            .entry(target.to_path_buf())
+            .or_insert_with(|| Arc::new(Mutex::new(UploadSession::new(file_size))));
+        Ok(session.clone())
+    }
+
+    // session is finished or discarded when it's no longer in registry
+    fn is_current(&self, target: &Path, session: &SharedUploadSession) -> bool {
+        self.get(target)
+            .is_some_and(|current| Arc::ptr_eq(&current, session))
+    }
+
+    fn remove(&self, target: &Path) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.remove(target);
+        }
+    }
+
+    // abort sessions inactive for timeout, busy sessions are active so they are skipped
+    fn expire(&self, timeout: Duration) {
+        let mut sessions = match self.sessions.lock() {
+            Ok(sessions) => sessions,
+            Err(_) => return,
+        };
+        sessions.retain(|target, session| {
+            let session = match session.try_lock() {
+                Ok(session) => session,
+                Err(_) => return true,
+            };
+            if session.writing > 0 || session.last_active.elapsed() < timeout {
+                return true;
+            }
+            debug!("expire(), upload session expired, target={:?}", target);
+            if let Some(upload) = &session.upload {
+                upload.abort();
+            }
+            false
+        });
+    }
+}
+
+// expire abandoned upload sessions in background periodically, it runs within tokio runtime
+// while aborting uploads runs in the pool, since it removes files
+fn start_expiring_uploads(upload_sessions: Arc<UploadSessionRegistry>, io_pool: FsPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPLOAD_SESSION_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let upload_sessions = upload_sessions.clone();
+            let result = io_pool
+                .run(move || {
+                    upload_sessions.expire(UPLOAD_SESSION_TIMEOUT);
+                    Ok(())
+                })
+                .await;
+            if let Err(e) = result {
+                debug!("start_expiring_uploads(), error={:?}", e);
+            }
+        }
+    });
+}
+
 pub struct ServaManagerServiceImpl {
     config: Config,
+    upload_sessions: Arc<UploadSessionRegistry>,
+    content_index: Option<Arc<ContentIndex>>,
+    jobs: Arc<JobRegistry>,
+    watchers: Arc<WatcherLimit>,
+    // blocking filesystem calls never run on the async runtime, slow ones are bounded separately
+    manage_pool: FsPool,
+    io_pool: FsPool,
+    stream_pool: FsPool,
+    // jobs and batches run long, so they never take workers of single operations and trash
+    job_pool: FsPool,
 }
 
 fn get_timestamp_in_ms(time: SystemTime) -> Result<i64, AnyError> {
@@ -86,285 +352,702 @@ fn get_timestamp_in_ms(time: SystemTime) -> Result<i64, AnyError> {
     Ok(time.duration_since(UNIX_EPOCH)?.as_millis() as i64)
 }
 
-fn validate_name(name: &str) -> Result<(), AnyError> {
-    if name.contains('/') || name.contains('\\') {
-        return Err(anyhow!("Name contains invalid character: {}", name));
+async fn receive_upload_data(
+    stream: &mut Streaming<UploadFileRequest>,
+    writer: &mut UploadFileWriter,
+) -> Result<(), AnyError> {
+    while let Some(message) = stream.message().await? {
+        match message.content {
+            Some(upload_file_request::Content::Data(data)) => writer.write(&data).await?,
+            _ => return Err(anyhow!("Only data could be sent after header")),
+        }
     }
     Ok(())
 }
 
-fn get_upload_path_names(
+fn get_error_status<E>(e: E) -> Status
+where
+    E: Into<AnyError>,
+{
+    ServaError::from(e.into()).into()
+}
+
+fn get_trash_item(item: TrashItem, retention: Option<Duration>) -> proto::TrashItem {
+    let expires_timestamp_in_ms = match retention {
+        Some(retention) => item.info.deleted_timestamp_in_ms + retention.as_millis() as i64,
+        None => 0,
+    };
+    proto::TrashItem {
+        id: item.id,
+        original_path: item.info.original_path,
+        deleted_timestamp_in_ms: item.info.deleted_timestamp_in_ms,
+        is_dir: item.info.is_dir,
+        size: item.info.size,
+        expires_timestamp_in_ms,
+    }
+}
+
+fn get_conflict_policy(policy: proto::ConflictPolicy) -> ConflictPolicy {
+    match policy {
+        proto::ConflictPolicy::Fail => ConflictPolicy::Fail,
+        proto::ConflictPolicy::Overwrite => ConflictPolicy::Overwrite,
+        proto::ConflictPolicy::Rename => ConflictPolicy::Rename,
+        proto::ConflictPolicy::Skip => ConflictPolicy::Skip,
+    }
+}
+
+fn is_manage_allowed(permission: &UserPermission, operation: Operation) -> bool {
+    match operation {
+        Operation::CreateDir => permission.create,
+        Operation::CopyFile => permission.copy,
+        Operation::DeleteFile => permission.delete,
+        Operation::MoveFile => permission.r#move,
+        Operation::RenameFile => permission.rename,
+    }
+}
+
+// execute one manage operation, progress is reported only when copying, or moving across filesystems
+fn execute_manage_request(
     root: &Path,
-    dir_path: &str,
-    file_name: &str,
-) -> Result<(PathBuf, PathBuf, PathBuf), AnyError> {
-    // get valid full_path for directory only
-    let full_path = get_valid_joined_path(root, dir_path)?;
-    // validate file_name
-    let _ = validate_name(file_name)?;
-    // get the full path name of the file to be uploaded
-    let target_path_name = full_path.join(file_name);
-    // get the full path name of the temporary file to be used
-    let temp_path_name = full_path.join(format!("{}.{}", file_name, UPLOAD_FILE_SUFFIX));
-    Ok((full_path, target_path_name, temp_path_name))
-}
-
-fn write_file(target: &Path, data: &Vec<u8>) -> Result<(), AnyError> {
-    Ok(std::fs::write(target, data)?)
-}
-
-fn write_first_chunk(
-    target: &Path,
-    temp: &Path,
-    data: &Vec<u8>,
-    file_size: u64,
+    request: &ManageDirOrFileRequest,
+    symlinks: SymlinkPolicy,
+    on_progress: &mut ProgressHandler,
 ) -> Result<(), AnyError> {
-    trace!("write_first_chunk()");
-    // write an empty target file as a placeholder
-    std::fs::write(target, &[])?;
-    write_chunk(temp, data, 0, file_size, true)?;
-    Ok(())
+    let file_path_name = request.file_path_name.as_str();
+    let dir_path = request.dir_path.as_str();
+    let target = request.target.as_str();
+    let options = ManageOptions {
+        symlinks,
+        on_conflict: get_conflict_policy(request.on_conflict()),
+    };
+    match request.operation() {
+        Operation::CreateDir => create_dir(root, dir_path, target, &options),
+        Operation::CopyFile => copy_file(root, file_path_name, dir_path, &options, on_progress),
+        Operation::DeleteFile => delete_file(root, file_path_name, symlinks),
+        Operation::MoveFile => move_file(root, file_path_name, dir_path, &options, on_progress),
+        Operation::RenameFile => rename_file(root, file_path_name, target, &options),
+    }
 }
 
-fn write_chunk(
-    temp: &Path,
-    data: &Vec<u8>,
-    offset: u64,
-    file_size: u64,
-    new_file: bool,
-) -> Result<(), AnyError> {
-    trace!("write_chunk()");
-    let data_size = data.len();
-    if (offset + data_size as u64) > file_size {
-        return Err(anyhow!(
-            "data exceeds file size, offset={}, data_size={}, file_size={}",
-            offset,
-            data_size,
-            file_size
-        ));
+fn get_batch_progress(index: u32, total_operations: u32, progress: &CopyProgress) -> BatchProgress {
+    BatchProgress {
+        index,
+        total_operations,
+        copied_files: progress.copied_files,
+        total_files: progress.total_files,
+        copied_bytes: progress.copied_bytes,
+        total_bytes: progress.total_bytes,
     }
-    let temp_file = match new_file {
-        true => std::fs::File::create(temp)?,
-        false => std::fs::OpenOptions::new().write(true).open(temp)?,
+}
+
+fn get_batch_result(index: u32, result: &Result<(), Status>) -> BatchResult {
+    match result {
+        Ok(_) => BatchResult {
+            index,
+            code: Code::Ok as i32,
+            message: String::new(),
+        },
+        Err(status) => BatchResult {
+            index,
+            code: status.code() as i32,
+            message: status.message().to_string(),
+        },
+    }
+}
+
+fn get_job(info: JobInfo) -> proto::Job {
+    let state = match info.state {
+        JobState::Queued => job::State::Queued,
+        JobState::Running => job::State::Running,
+        JobState::Succeeded => job::State::Succeeded,
+        JobState::Failed => job::State::Failed,
+        JobState::Cancelled => job::State::Cancelled,
     };
-    #[cfg(target_os = "windows")]
-    temp_file.seek_write(data, offset)?;
-    #[cfg(not(target_os = "windows"))]
-    temp_file.write_all_at(data, offset)?;
-    temp_file.sync_all()?;
-    Ok(())
+    proto::Job {
+        id: info.id,
+        state: state as i32,
+        description: info.description,
+        created_timestamp_in_ms: info.created_timestamp_in_ms,
+        finished_timestamp_in_ms: info.finished_timestamp_in_ms,
+        progress: Some(get_batch_progress(
+            info.index,
+            info.total_operations,
+            &info.progress,
+        )),
+        results: info
+            .results
+            .into_iter()
+            .map(|result| BatchResult {
+                index: result.index,
+                code: result.code,
+                message: result.message,
+            })
+            .collect(),
+    }
 }
 
-fn write_last_chunk(
-    target: &Path,
-    temp: &Path,
-    data: &Vec<u8>,
-    offset: u64,
-    file_size: u64,
-) -> Result<(), AnyError> {
-    trace!("write_last_chunk()");
-    write_chunk(temp, data, offset, file_size, false)?;
-    std::fs::remove_file(target)?;
-    std::fs::rename(temp, target)?;
-    Ok(())
+fn get_manage_description(request: &ManageDirOrFileRequest) -> String {
+    let operation = match request.operation() {
+        Operation::CreateDir => return format!("create {}", request.target),
+        Operation::CopyFile => "copy",
+        Operation::DeleteFile => return format!("delete {}", request.file_path_name),
+        Operation::MoveFile => "move",
+        Operation::RenameFile => {
+            return format!("rename {} to {}", request.file_path_name, request.target)
+        }
+    };
+    format!(
+        "{} {} to {}",
+        operation, request.file_path_name, request.dir_path
+    )
 }
 
-fn remove_files(target_path_name: &Path, temp_path_name: &Path) -> Result<(), AnyError> {
-    if target_path_name.exists() {
-        remove_file(target_path_name)?;
+// run operations of job in order, it stops when cancelled, or at first failure if asked
+fn run_job<F>(job: &Job, total_operations: u32, stop_on_error: bool, execute: F)
+where
+    F: Fn(u32, &mut ProgressHandler) -> Result<(), AnyError>,
+{
+    if !job.start() {
+        return;
     }
-    if temp_path_name.exists() {
-        remove_file(temp_path_name)?;
+    for index in 0..total_operations {
+        if job.is_cancelled() {
+            break;
+        }
+        let mut on_progress = |progress: &CopyProgress| {
+            job.set_progress(index, progress);
+            !job.is_cancelled()
+        };
+        let result = match execute(index, &mut on_progress) {
+            Err(_) if job.is_cancelled() => Err(Status::new(Code::Cancelled, "Cancelled")),
+            result => result.map_err(get_error_status),
+        };
+        trace!("run_job(), index={}, result={:?}", index, result);
+        let failed = result.is_err();
+        let result = get_batch_result(index, &result);
+        job.add_result(JobResult {
+            index,
+            code: result.code,
+            message: result.message,
+        });
+        if failed && stop_on_error {
+            break;
+        }
     }
-    Ok(())
+    job.finish();
 }
 
-impl ServaManagerServiceImpl {
-    fn get_stripped_path_string(&self, path: &Path) -> Result<String, AnyError> {
-        // strip root from path
-        let stripped_path = path.strip_prefix(&self.config.root_path)?;
-        let path = stripped_path
-            .to_str()
-            .ok_or_else(|| anyhow!("stripped_path.to_str() failed"))?;
-        Ok(path.to_string())
-    }
-
-    fn get_dir_entries(&self, dir: &str) -> Result<DirEntriesTuple, AnyError> {
-        // get valid full_path
-        let full_path = get_valid_joined_path(&self.config.root_path, dir)?;
-        trace!("get_dir_entries(), full_path={:?}", &full_path);
-        // read entries from path, fill metadata to result
-        let entries = read_dir(full_path)?;
-        let mut directories = vec![];
-        let mut files = vec![];
-        for entry in entries {
-            let entry = entry?;
-            let metadata = entry.metadata()?;
-            let entry_path = entry.path();
-            trace!("entry_path={:?}", &entry_path,);
-            let stripped_path = self.get_stripped_path_string(&entry_path)?;
-            trace!("stripped_path={}", &stripped_path);
-            let modified_timestamp_in_ms = get_timestamp_in_ms(metadata.modified()?)?;
-            // only handle dir and file here, skip symlinks
-            match (metadata.is_dir(), metadata.is_file()) {
-                (true, false) => directories.push(Directory {
-                    path: stripped_path,
-                    modified_timestamp_in_ms,
-                }),
-                (false, true) => files.push(File {
-                    path: stripped_path,
-                    modified_timestamp_in_ms,
-                    size: metadata.len(),
-                }),
-                (_, _) => {}
+fn run_manage_job(
+    job: &Job,
+    user: &UserProfile,
+    request: &BatchManageRequest,
+    symlinks: SymlinkPolicy,
+) {
+    let total_operations = request.operations.len() as u32;
+    run_job(
+        job,
+        total_operations,
+        request.stop_on_error,
+        |index, on_progress| {
+            let operation = &request.operations[index as usize];
+            match is_manage_allowed(&user.permission, operation.operation()) {
+                true => execute_manage_request(&user.root, operation, symlinks, on_progress),
+                false => Err(
+                    io::Error::new(io::ErrorKind::PermissionDenied, "Manage not allowed").into(),
+                ),
             }
+        },
+    );
+}
+
+fn get_archive_format(format: &str) -> Result<ArchiveFormat, AnyError> {
+    match format {
+        "" => Ok(ArchiveFormat::Zip),
+        format => format.parse(),
+    }
+}
+
+fn run_archive_job(job: &Job, user: &UserProfile, archive: &ArchiveJob, symlinks: SymlinkPolicy) {
+    run_job(job, 1, false, |_, on_progress| {
+        let options = ManageOptions {
+            symlinks,
+            on_conflict: get_conflict_policy(archive.on_conflict()),
+        };
+        get_archive_format(&archive.format).and_then(|format| {
+            create_archive(
+                &user.root,
+                &archive.dir_path,
+                &archive.paths,
+                format,
+                &archive.target,
+                &options,
+                on_progress,
+            )
+        })
+    });
+}
+
+fn get_stripped_path_string(root: &Path, path: &Path) -> Result<String, AnyError> {
+    // strip root from path
+    let stripped_path = path.strip_prefix(root)?;
+    let path = stripped_path
+        .to_str()
+        .ok_or_else(|| anyhow!("stripped_path.to_str() failed"))?;
+    Ok(path.to_string())
+}
+
+fn get_entry_kind(file_type: &FileType) -> EntryKind {
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_socket() {
+            return EntryKind::Socket;
+        } else if file_type.is_fifo() {
+            return EntryKind::Fifo;
+        } else if file_type.is_block_device() {
+            return EntryKind::BlockDevice;
+        } else if file_type.is_char_device() {
+            return EntryKind::CharDevice;
+        }
+    }
+    match (
+        file_type.is_symlink(),
+        file_type.is_dir(),
+        file_type.is_file(),
+    ) {
+        (true, _, _) => EntryKind::Symlink,
+        (_, true, _) => EntryKind::Directory,
+        (_, _, true) => EntryKind::RegularFile,
+        _ => EntryKind::Other,
+    }
+}
+
+// mode bits, uid and gid
+#[cfg(not(target_os = "windows"))]
+fn get_ownership(metadata: &Metadata) -> (u32, u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.mode(), metadata.uid(), metadata.gid())
+}
+
+#[cfg(target_os = "windows")]
+fn get_ownership(_metadata: &Metadata) -> (u32, u32, u32) {
+    (0, 0, 0)
+}
+
+fn get_search_result(
+    root: &Path,
+    path: &Path,
+    metadata: &Metadata,
+) -> Result<search_files_response::Result, AnyError> {
+    get_entry_result(root, path, metadata, metadata)
+}
+
+// metadata is of the target when symlink is followed, symlink_metadata is of the entry itself
+fn get_entry_result(
+    root: &Path,
+    path: &Path,
+    metadata: &Metadata,
+    symlink_metadata: &Metadata,
+) -> Result<search_files_response::Result, AnyError> {
+    let path_string = get_stripped_path_string(root, path)?;
+    let modified_timestamp_in_ms = get_timestamp_in_ms(metadata.modified()?)?;
+    let get_optional_timestamp_in_ms = |time: std::io::Result<SystemTime>| {
+        time.ok()
+            .and_then(|time| get_timestamp_in_ms(time).ok())
+            .unwrap_or_default()
+    };
+    let created_timestamp_in_ms = get_optional_timestamp_in_ms(metadata.created());
+    let accessed_timestamp_in_ms = get_optional_timestamp_in_ms(metadata.accessed());
+    let kind = get_entry_kind(&symlink_metadata.file_type()) as i32;
+    let symlink_target = match symlink_metadata.is_symlink() {
+        true => std::fs::read_link(path)?.to_string_lossy().to_string(),
+        false => String::new(),
+    };
+    let (mode, uid, gid) = get_ownership(metadata);
+    let hidden = path
+        .file_name()
+        .map(|name| is_hidden(&name.to_string_lossy()))
+        .unwrap_or(false);
+    Ok(match metadata.is_dir() {
+        true => search_files_response::Result::Directory(Directory {
+            path: path_string,
+            modified_timestamp_in_ms,
+            kind,
+            symlink_target,
+            mode,
+            uid,
+            gid,
+            created_timestamp_in_ms,
+            accessed_timestamp_in_ms,
+            hidden,
+        }),
+        false => search_files_response::Result::File(File {
+            path: path_string,
+            modified_timestamp_in_ms,
+            size: match metadata.is_file() {
+                true => metadata.len(),
+                false => 0,
+            },
+            has_thumbnail: has_thumbnail(path, metadata),
+            kind,
+            symlink_target,
+            mode,
+            uid,
+            gid,
+            created_timestamp_in_ms,
+            accessed_timestamp_in_ms,
+            hidden,
+        }),
+    })
+}
+
+fn get_content_search_result(
+    root: &Path,
+    path: &Path,
+    query: &str,
+    max_lines: usize,
+) -> Result<Option<SearchContentResponse>, AnyError> {
+    let metadata = path.metadata()?;
+    let file = match get_search_result(root, path, &metadata)? {
+        search_files_response::Result::File(file) => file,
+        _ => return Ok(None),
+    };
+    // index may be outdated, or words are matched in different lines
+    let lines: Vec<LineMatch> = get_matched_lines(path, query, max_lines)?
+        .into_iter()
+        .map(|(line_number, line)| LineMatch { line_number, line })
+        .collect();
+    if lines.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(SearchContentResponse {
+        file: Some(file),
+        lines,
+    }))
+}
+
+fn get_watch_event(root: &Path, change: Change) -> Result<WatchEvent, AnyError> {
+    // changes in trash are hidden, and restoring from trash is creating
+    let (path, kind) = match change.kind {
+        ChangeKind::Renamed(new_path)
+            if is_in_trash(change.path.strip_prefix(root).unwrap_or(&change.path)) =>
+        {
+            (new_path, ChangeKind::Created)
         }
-        Ok((directories, files))
+        kind => (change.path, kind),
+    };
+    let path = get_stripped_path_string(root, &path)?;
+    if is_in_trash(Path::new(&path)) {
+        return Err(anyhow!("change in trash"));
     }
+    let (kind, new_path) = match kind {
+        ChangeKind::Created => (watch_event::Kind::Created, String::new()),
+        ChangeKind::Modified => (watch_event::Kind::Modified, String::new()),
+        ChangeKind::Deleted => (watch_event::Kind::Deleted, String::new()),
+        // moved out of root or into trash is deleted for the user
+        ChangeKind::Renamed(new_path) => match get_stripped_path_string(root, &new_path) {
+            Ok(new_path) if !is_in_trash(Path::new(&new_path)) => {
+                (watch_event::Kind::Renamed, new_path)
+            }
+            _ => (watch_event::Kind::Deleted, String::new()),
+        },
+    };
+    Ok(WatchEvent {
+        path,
+        kind: kind as i32,
+        new_path,
+    })
+}
+
+fn get_search_filter(request: &SearchFilesRequest) -> Result<SearchFilter, AnyError> {
+    let name = match (request.name_pattern.is_empty(), request.use_regex) {
+        (true, _) => None,
+        (false, true) => Some(
+            RegexBuilder::new(&request.name_pattern)
+                .case_insensitive(!request.case_sensitive)
+                .build()?,
+        ),
+        (false, false) => Some(glob_to_regex(
+            &request.name_pattern,
+            request.case_sensitive,
+        )?),
+    };
+    // 0 means no limit, and times before epoch are invalid
+    let get_time = |timestamp_in_ms: i64| match timestamp_in_ms {
+        0 => Ok(None),
+        1.. => UNIX_EPOCH
+            .checked_add(Duration::from_millis(timestamp_in_ms as u64))
+            .map(Some)
+            .ok_or_else(|| anyhow!("timestamp {} is out of range", timestamp_in_ms)),
+        _ => Err(anyhow!("timestamp {} is negative", timestamp_in_ms)),
+    };
+    Ok(SearchFilter {
+        name,
+        min_size: Some(request.min_size).filter(|size| *size > 0),
+        max_size: Some(request.max_size).filter(|size| *size > 0),
+        min_modified: get_time(request.min_modified_timestamp_in_ms)?,
+        max_modified: get_time(request.max_modified_timestamp_in_ms)?,
+        include_dirs: request.include_dirs,
+    })
+}
 
-    #[allow(clippy::too_many_arguments)]
+fn get_list_options(
+    request: &ListDirRequest,
+    symlinks: SymlinkPolicy,
+) -> Result<ListOptions, AnyError> {
+    let sort_by = match list_dir_request::SortBy::from_i32(request.sort_by) {
+        Some(list_dir_request::SortBy::Name) => SortBy::Name,
+        Some(list_dir_request::SortBy::Size) => SortBy::Size,
+        Some(list_dir_request::SortBy::Modified) => SortBy::Modified,
+        None => return Err(anyhow!("invalid sort_by {}", request.sort_by)),
+    };
+    let name_filter = match request.name_filter.is_empty() {
+        true => None,
+        false => Some(glob_to_regex(&request.name_filter, false)?),
+    };
+    let page_size = match request.page_size {
+        0 => DEFAULT_PAGE_SIZE,
+        page_size => page_size.min(MAX_PAGE_SIZE),
+    };
+    let descending = request.order == list_dir_request::Order::Descending as i32;
+    let page_token = match request.page_token.is_empty() {
+        true => None,
+        false => Some(SortKey::from_token(
+            &request.page_token,
+            sort_by,
+            descending,
+        )?),
+    };
+    Ok(ListOptions {
+        sort_by,
+        descending,
+        name_filter,
+        show_hidden: !request.hide_hidden,
+        page_size: page_size as usize,
+        page_token,
+        symlinks,
+    })
+}
+
+fn get_dir_entries(
+    root: &Path,
+    dir: &str,
+    options: &ListOptions,
+    symlinks: SymlinkPolicy,
+) -> Result<ListDirResponse, AnyError> {
+    // get valid full_path
+    let full_path = resolve_path(root, dir, symlinks)?;
+    trace!("get_dir_entries(), full_path={:?}", &full_path);
+    let page = list_dir_page(&full_path, root, options)?;
+    let mut directories = vec![];
+    let mut files = vec![];
+    for entry in page.entries {
+        let result = get_entry_result(root, &entry.path, &entry.metadata, &entry.symlink_metadata)?;
+        match result {
+            search_files_response::Result::Directory(directory) => directories.push(directory),
+            search_files_response::Result::File(file) => files.push(file),
+        }
+    }
+    Ok(ListDirResponse {
+        dir_path: dir.to_string(),
+        directories,
+        files,
+        next_page_token: page.next_page_token,
+        total_directories: page.total_dirs,
+        total_files: page.total_files,
+    })
+}
+
+impl UploadSessionRegistry {
     fn save_file_chunk(
         &self,
-        dir_path: &str,
-        file_name: &str,
-        file_size: u64,
-        chunk_data: &Vec<u8>,
-        chunk_id: u64,
-        chunk_count: u64,
-        chunk_offset: u64,
+        root: &Path,
+        request: &UploadFileChunkRequest,
+        symlinks: SymlinkPolicy,
     ) -> Result<(), AnyError> {
+        let dir_path = request.dir_path.as_str();
+        let file_name = request.file_name.as_str();
+        let file_size = request.file_size;
+        let file_hash = request.file_hash.as_str();
+        let chunk_data = &request.chunk_data;
+        let chunk_offset = request.chunk_offset;
+        let chunk_hash = request.chunk_hash.as_str();
+        let on_conflict = get_conflict_policy(request.on_conflict());
         // get related dir and file path names
-        let (full_path, target_path_name, temp_path_name) =
-            get_upload_path_names(&self.config.root_path, dir_path, file_name)?;
+        let (full_path, target_path_name) =
+            get_upload_path_names(root, dir_path, file_name, symlinks)?;
         trace!("save_file_chunk(), full_path={:?}", &full_path);
         trace!(
             "save_file_chunk(), target_path_name={:?}",
             &target_path_name
         );
-        trace!("save_file_chunk(), temp_path_name={:?}", &temp_path_name);
 
-        // check if this is the first or last chunk, or both
-        let first_chunk = chunk_id == 0;
-        let last_chunk = chunk_id == (chunk_count - 1);
-        trace!(
-            "save_file_chunk(), first_chunk={}, last_chunk={}",
-            first_chunk,
-            last_chunk
-        );
+        // bad chunk is rejected alone, the session keeps received ranges so the chunk could be sent again
+        verify_hash("chunk", chunk_hash, || Ok(get_sha256_of_data(chunk_data)))?;
 
-        // let mut succeeded = false;
-        let write_result = match (first_chunk, last_chunk) {
-            (true, true) => write_file(&target_path_name, chunk_data),
-            (true, false) => {
-                write_first_chunk(&target_path_name, &temp_path_name, chunk_data, file_size)
-            }
-            (false, false) => {
-                write_chunk(&temp_path_name, chunk_data, chunk_offset, file_size, false)
+        // find the session of this upload, chunks could arrive in any order
+        let shared_session = self.get_or_create(&target_path_name, file_size)?;
+        let mut session = shared_session
+            .lock()
+            .map_err(|_| anyhow!("upload session poisoned"))?;
+        if !self.is_current(&target_path_name, &shared_session) {
+            return Err(anyhow!("upload session is finished or discarded"));
+        }
+        if session.file_size != file_size {
+            // same target with different size means a brand new upload
+            trace!("save_file_chunk(), file size changed, restart session");
+            if let Some(upload) = &session.upload {
+                upload.abort();
             }
-            (false, true) => write_last_chunk(
-                &target_path_name,
-                &temp_path_name,
-                chunk_data,
-                chunk_offset,
-                file_size,
-            ),
+            *session = UploadSession::new(file_size);
+        }
+
+        session.last_active = Instant::now();
+        let chunk_end = chunk_offset
+            .checked_add(chunk_data.len() as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "chunk offset overflow"))?;
+
+        // conflict is resolved only once for the session
+        let upload = match session.upload.clone() {
+            Some(upload) => upload,
+            None => match UploadTarget::start(root, &target_path_name, on_conflict) {
+                Ok(Some(upload)) => upload,
+                // skipped by conflict policy, nothing is written
+                Ok(None) => {
+                    trace!("save_file_chunk(), skipped");
+                    self.remove(&target_path_name);
+                    return Ok(());
+                }
+                // nothing received yet, so there is nothing to resume
+                Err(e) => {
+                    self.remove(&target_path_name);
+                    return Err(e);
+                }
+            },
         };
-        if write_result.is_err() {
-            trace!("save_file_chunk(), write_result={:?}", write_result);
-            let _ = remove_files(&target_path_name, &temp_path_name);
-            write_result?;
+        session.upload = Some(upload.clone());
+
+        // write it by position without the lock, so chunks of the same upload are written at once,
+        // failed chunk is not counted as received
+        session.writing += 1;
+        drop(session);
+        let result = write_chunk(&upload.temp, chunk_data, chunk_offset, file_size);
+        let mut session = shared_session
+            .lock()
+            .map_err(|_| anyhow!("upload session poisoned"))?;
+        session.writing -= 1;
+        result?;
+        // session might be finished, discarded or restarted with another size while writing
+        let is_same_upload =
+            matches!(&session.upload, Some(current) if current.temp == upload.temp);
+        if !is_same_upload || !self.is_current(&target_path_name, &shared_session) {
+            return Err(anyhow!("upload session is finished or discarded"));
         }
-        Ok(())
+        session.add_received(chunk_offset..chunk_end);
+        trace!(
+            "save_file_chunk(), received_size={}, file_size={}",
+            session.get_received_size(),
+            file_size
+        );
+        if !session.is_complete() {
+            return Ok(());
+        }
+
+        // finish only when every byte has arrived, the whole file is sent again when it's corrupted,
+        // it's done with the lock, so later chunks find the session removed instead of finishing again
+        let result = upload.finish(file_hash);
+        if result.is_err() {
+            trace!("save_file_chunk(), finish result={:?}", result);
+            upload.abort();
+        }
+        self.remove(&target_path_name);
+        result
     }
 
-    fn discard_file_chunk(&self, dir_path: &str, file_name: &str) -> Result<(), AnyError> {
-        let (full_path, target_path_name, temp_path_name) =
-            get_upload_path_names(&self.config.root_path, dir_path, file_name)?;
+    fn discard_file_chunk(
+        &self,
+        root: &Path,
+        dir_path: &str,
+        file_name: &str,
+        symlinks: SymlinkPolicy,
+    ) -> Result<(), AnyError> {
+        let (full_path, target_path_name) =
+            get_upload_path_names(root, dir_path, file_name, symlinks)?;
         trace!("discard_file_chunk(), full_path={:?}", &full_path);
         trace!(
             "discard_file_chunk(), target_path_name={:?}",
             &target_path_name
         );
-        trace!("discard_file_chunk(), temp_path_name={:?}", &temp_path_name);
-        remove_files(&target_path_name, &temp_path_name)?;
-        Ok(())
-    }
-
-    fn create_dir(&self, dir_path: &str, dir_name: &str) -> Result<(), AnyError> {
-        let full_path = get_valid_joined_path(&self.config.root_path, dir_path)?;
-        let _ = validate_name(dir_name)?;
-        let target = full_path.join(dir_name);
-        trace!("create_dir(), target={:?}", target);
-        std::fs::create_dir(target)?;
-        Ok(())
-    }
-
-    fn copy_file(&self, file_path_name: &str, dir_path: &str) -> Result<(), AnyError> {
-        let from = get_valid_joined_path(&self.config.root_path, file_path_name)?;
-        if from.is_file() {
-            let file_name = from.file_name().ok_or_else(|| anyhow!("no file name"))?;
-            let to = get_valid_joined_path(&self.config.root_path, dir_path)?.join(file_name);
-            trace!("copy_file(), is_file, from={:?}, to={:?}", &from, &to);
-            std::fs::copy(&from, &to)?;
-        } else if from.is_dir() {
-            use fs_extra::dir::{copy, CopyOptions};
-            let to = get_valid_joined_path(&self.config.root_path, dir_path)?;
-            trace!("copy_file(), is_dir, from={:?}, to={:?}", &from, &to);
-            copy(from, to, &CopyOptions::new())?;
-        }
-        Ok(())
-    }
-
-    fn delete_file(&self, file_path_name: &str) -> Result<(), AnyError> {
-        let full_name = get_valid_joined_path(&self.config.root_path, file_path_name)?;
-        trace!("delete_file(), full_name={:?}", &full_name);
-        if full_name.is_file() {
-            std::fs::remove_file(full_name)?;
-        } else if full_name.is_dir() {
-            std::fs::remove_dir_all(full_name)?;
+        let session = self.get(&target_path_name);
+        self.remove(&target_path_name);
+        let upload = session
+            .as_ref()
+            .and_then(|session| session.lock().ok())
+            .and_then(|session| session.upload.clone());
+        // nothing is removed when the upload is unknown, entries of users are never touched
+        if let Some(upload) = upload {
+            upload.abort();
         }
         Ok(())
     }
 
-    fn move_file(&self, file_path_name: &str, dir_path: &str) -> Result<(), AnyError> {
-        let from = get_valid_joined_path(&self.config.root_path, file_path_name)?;
-        let file_name = from.file_name().ok_or_else(|| anyhow!("no file name"))?;
-        let to = get_valid_joined_path(&self.config.root_path, dir_path)?.join(file_name);
-        trace!("move_file(), from={:?}, to={:?}", &from, &to);
-        std::fs::rename(&from, &to)?;
-        Ok(())
-    }
-
-    fn rename_file(&self, file_path_name: &str, new_name: &str) -> Result<(), AnyError> {
-        let from = get_valid_joined_path(&self.config.root_path, file_path_name)?;
-        let _ = validate_name(new_name)?;
-        let mut to = from.clone();
-        to.set_file_name(new_name);
-        trace!("rename_file(), from={:?}, to={:?}", &from, &to);
-        std::fs::rename(&from, &to)?;
-        Ok(())
+    fn get_upload_status(
+        &self,
+        root: &Path,
+        dir_path: &str,
+        file_name: &str,
+        symlinks: SymlinkPolicy,
+    ) -> Result<QueryUploadStatusResponse, AnyError> {
+        let (_, target_path_name) = get_upload_path_names(root, dir_path, file_name, symlinks)?;
+        trace!(
+            "get_upload_status(), target_path_name={:?}",
+            &target_path_name
+        );
+        let session = match self.get(&target_path_name) {
+            Some(session) => session,
+            None => return Ok(QueryUploadStatusResponse::default()),
+        };
+        let session = session
+            .lock()
+            .map_err(|_| anyhow!("upload session poisoned"))?;
+        let missing_ranges = session
+            .get_missing()
+            .into_iter()
+            .map(|range| ByteRange {
+                offset: range.start,
+                size: range.end - range.start,
+            })
+            .collect();
+        Ok(QueryUploadStatusResponse {
+            in_progress: true,
+            file_size: session.file_size,
+            received_size: session.get_received_size(),
+            missing_ranges,
+        })
     }
 }
 
 #[tonic::async_trait]
 impl ServaManager for ServaManagerServiceImpl {
     async fn list_dir(&self, request: TonicListDirReq) -> Result<TonicListDirResp, Status> {
-        let dir_path = request.get_ref().dir_path.clone();
-        debug!("list_dir(), dir_path={}", dir_path);
-        let (directories, files) = self
-            .get_dir_entries(&dir_path)
-            .map_err(|e| Status::new(Code::Internal, e.to_string()))?;
-        let reply = ListDirResponse {
-            dir_path,
-            directories,
-            files,
-        };
+        let user = get_user_or_return!(request);
+        let request = request.into_inner();
+        debug!("list_dir(), user={}, request={:?}", user.name, request);
+        let options = get_list_options(&request, self.config.symlinks)
+            .map_err(|e| Status::new(Code::InvalidArgument, e.to_string()))?;
+        let symlinks = self.config.symlinks;
+        let reply = self
+            .io_pool
+            .run(move || get_dir_entries(&user.root, &request.dir_path, &options, symlinks))
+            .await
+            .map_err(get_error_status)?;
         Ok(Response::new(reply))
     }
 
-    async fn get_config(&self, _req: TonicGetCfgReq) -> Result<TonicGetCfgResp, Status> {
-        debug!("get_config()");
-        // Discard request since no information inside
+    async fn get_config(&self, request: TonicGetCfgReq) -> Result<TonicGetCfgResp, Status> {
+        let user = get_user_or_return!(request);
+        debug!("get_config(), user={}", user.name);
+        // Discard message since no information inside
         let address = self
             .config
             .available_ip
@@ -376,9 +1059,9 @@ impl ServaManager for ServaManagerServiceImpl {
             .collect();
         let reply = GetConfigResponse {
             root: self.config.root_relative.clone(),
-            root_canonical: self.config.root_absolute.clone(),
+            root_canonical: user.get_root_string(),
             prefix: self.config.prefix.clone(),
-            permission: Some(__self.config.permission.clone()),
+            permission: Some(Permission::from(&user.permission)),
             address,
         };
         Ok(Response::new(reply))
@@ -388,66 +1071,656 @@ impl ServaManager for ServaManagerServiceImpl {
         &self,
         request: TonicUploadFileChunkReq,
     ) -> Result<TonicUploadFileChunkResp, Status> {
-        if !self.config.allow_upload {
+        let user = get_user_or_return!(request);
+        if !user.permission.upload {
             return Err(Status::new(Code::PermissionDenied, "Upload not allowed"));
         }
-        let dir_path = request.get_ref().dir_path.as_str();
-        let file_name = request.get_ref().file_name.as_str();
-        let file_size = request.get_ref().file_size;
-        let _file_hash = request.get_ref().file_hash.as_str(); // no use for now
-        let abort = request.get_ref().abort;
-        let chunk_data = &request.get_ref().chunk_data;
-        let chunk_id = request.get_ref().chunk_id;
-        let chunk_count = request.get_ref().chunk_count;
-        let chunk_offset = request.get_ref().chunk_offset;
-        let _chunk_size = request.get_ref().chunk_size; // no use for now
-        let _chunk_hash = request.get_ref().chunk_hash.as_str(); // no use for now
+        let request = request.into_inner();
         debug!(
-            "upload_file_chunk(), dir={}, file={}, abort={}",
-            dir_path, file_name, abort
+            "upload_file_chunk(), dir={}, file={}, abort={}, chunk={}/{}",
+            request.dir_path,
+            request.file_name,
+            request.abort,
+            request.chunk_id,
+            request.chunk_count
         );
-        let result = match abort {
-            false => self.save_file_chunk(
-                dir_path,
-                file_name,
-                file_size,
-                chunk_data,
-                chunk_id,
-                chunk_count,
-                chunk_offset,
-            ),
-            true => self.discard_file_chunk(dir_path, file_name),
-        };
+        let symlinks = self.config.symlinks;
+        let upload_sessions = self.upload_sessions.clone();
+        let result = self
+            .io_pool
+            .run(move || match request.abort {
+                false => upload_sessions.save_file_chunk(&user.root, &request, symlinks),
+                true => upload_sessions.discard_file_chunk(
+                    &user.root,
+                    &request.dir_path,
+                    &request.file_name,
+                    symlinks,
+                ),
+            })
+            .await;
         match result {
-            Err(e) => Err(Status::new(Code::Internal, e.to_string())),
+            Err(e) => Err(get_error_status(e)),
             Ok(_) => Ok(Response::new(UploadFileChunkResponse {})),
         }
     }
 
+    async fn upload_file(
+        &self,
+        request: TonicUploadFileReq,
+    ) -> Result<TonicUploadFileResp, Status> {
+        let user = get_user_or_return!(request);
+        if !user.permission.upload {
+            return Err(Status::new(Code::PermissionDenied, "Upload not allowed"));
+        }
+        let mut stream = request.into_inner();
+
+        // the first message must be the header
+        let header = match stream.message().await?.and_then(|message| message.content) {
+            Some(upload_file_request::Content::Header(header)) => header,
+            _ => {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    "Header must be sent first",
+                ))
+            }
+        };
+        debug!(
+            "upload_file(), dir={}, file={}, size={}",
+            header.dir_path, header.file_name, header.file_size
+        );
+        let (_, target_path_name) = get_upload_path_names(
+            &user.root,
+            &header.dir_path,
+            &header.file_name,
+            self.config.symlinks,
+        )
+        .map_err(get_error_status)?;
+        let on_conflict = get_conflict_policy(header.on_conflict());
+        let root = user.root.clone();
+        let upload = self
+            .io_pool
+            .run(move || UploadTarget::start(&root, &target_path_name, on_conflict))
+            .await;
+        let upload = match upload {
+            Ok(Some(upload)) => upload,
+            // skipped by conflict policy, data is not received
+            Ok(None) => return Ok(Response::new(UploadFileResponse { received_size: 0 })),
+            Err(e) => return Err(get_error_status(e)),
+        };
+
+        // write all data messages to the same file handle
+        let result = async {
+            let mut writer = UploadFileWriter::new(upload.clone(), Some(header.file_size)).await?;
+            receive_upload_data(&mut stream, &mut writer).await?;
+            writer.finish(&header.file_hash, &self.io_pool).await
+        }
+        .await
+        .map_err(get_error_status);
+        if result.is_err() {
+            trace!("upload_file(), result={:?}", result);
+            let _ = self
+                .io_pool
+                .run(move || {
+                    upload.abort();
+                    Ok(())
+                })
+                .await;
+        }
+        let received_size = result?;
+        Ok(Response::new(UploadFileResponse { received_size }))
+    }
+
+    async fn query_upload_status(
+        &self,
+        request: TonicQueryUploadStatusReq,
+    ) -> Result<TonicQueryUploadStatusResp, Status> {
+        let user = get_user_or_return!(request);
+        if !user.permission.upload {
+            return Err(Status::new(Code::PermissionDenied, "Upload not allowed"));
+        }
+        let request = request.into_inner();
+        debug!(
+            "query_upload_status(), dir={}, file={}",
+            request.dir_path, request.file_name
+        );
+        let symlinks = self.config.symlinks;
+        let upload_sessions = self.upload_sessions.clone();
+        let reply = self
+            .io_pool
+            .run(move || {
+                upload_sessions.get_upload_status(
+                    &user.root,
+                    &request.dir_path,
+                    &request.file_name,
+                    symlinks,
+                )
+            })
+            .await
+            .map_err(get_error_status)?;
+        Ok(Response::new(reply))
+    }
+
     async fn manage_dir_or_file(
         &self,
         request: TonicManageDirOrFileReq,
     ) -> Result<TonicManageDirOrFileResp, Status> {
-        if !self.config.allow_manage {
+        let user = get_user_or_return!(request);
+        if !is_manage_allowed(&user.permission, request.get_ref().operation()) {
             return Err(Status::new(Code::PermissionDenied, "Manage not allowed"));
         }
-        let file_path_name = request.get_ref().file_path_name.as_str();
-        let dir_path = request.get_ref().dir_path.as_str();
-        let target = request.get_ref().target.as_str();
-        let operation = request.get_ref().operation();
-        let operation_result = match operation {
-            Operation::CreateDir => self.create_dir(dir_path, target),
-            Operation::CopyFile => self.copy_file(file_path_name, dir_path),
-            Operation::DeleteFile => self.delete_file(file_path_name),
-            Operation::MoveFile => self.move_file(file_path_name, dir_path),
-            Operation::RenameFile => self.rename_file(file_path_name, target),
-        };
-        let _ = operation_result.map_err(|e| Status::new(Code::Internal, e.to_string()))?;
+        let request = request.into_inner();
+        let symlinks = self.config.symlinks;
+        self.manage_pool
+            .run(move || execute_manage_request(&user.root, &request, symlinks, &mut |_| true))
+            .await
+            .map_err(get_error_status)?;
         Ok(Response::new(ManageDirOrFileResponse {}))
     }
+
+    type BatchManageStream = ReceiverStream<Result<BatchManageResponse, Status>>;
+
+    async fn batch_manage(
+        &self,
+        request: TonicBatchManageReq,
+    ) -> Result<TonicBatchManageResp, Status> {
+        let user = get_user_or_return!(request);
+        let request = request.into_inner();
+        debug!(
+            "batch_manage(), user={}, operations={}, stop_on_error={}",
+            user.name,
+            request.operations.len(),
+            request.stop_on_error
+        );
+
+        // execute in the job pool, it stops when the client cancels and the receiver is dropped
+        let (sender, receiver) = mpsc::channel(BATCH_CHANNEL_SIZE);
+        let symlinks = self.config.symlinks;
+        let job_pool = self.job_pool.clone();
+        let batch = move || {
+            let total_operations = request.operations.len() as u32;
+            for (index, operation) in request.operations.iter().enumerate() {
+                let index = index as u32;
+                if sender.is_closed() {
+                    break;
+                }
+                let mut last_progress: Option<Instant> = None;
+                let mut on_progress = |progress: &CopyProgress| {
+                    // progress is throttled, but the last one of every copy is sent
+                    let is_done = progress.copied_files == progress.total_files;
+                    if !is_done
+                        && matches!(last_progress, Some(last) if last.elapsed() < BATCH_PROGRESS_INTERVAL)
+                    {
+                        return !sender.is_closed();
+                    }
+                    last_progress = Some(Instant::now());
+                    let progress = get_batch_progress(index, total_operations, progress);
+                    let event = batch_manage_response::Event::Progress(progress);
+                    sender
+                        .blocking_send(Ok(BatchManageResponse { event: Some(event) }))
+                        .is_ok()
+                };
+                let result = match is_manage_allowed(&user.permission, operation.operation()) {
+                    true => {
+                        execute_manage_request(&user.root, operation, symlinks, &mut on_progress)
+                            .map_err(get_error_status)
+                    }
+                    false => Err(Status::new(Code::PermissionDenied, "Manage not allowed")),
+                };
+                trace!("batch_manage(), index={}, result={:?}", index, result);
+                let failed = result.is_err();
+                let result = get_batch_result(index, &result);
+                let event = batch_manage_response::Event::Result(result);
+                if sender
+                    .blocking_send(Ok(BatchManageResponse { event: Some(event) }))
+                    .is_err()
+                    || failed && request.stop_on_error
+                {
+                    break;
+                }
+            }
+            trace!("batch_manage(), stopped");
+            Ok(())
+        };
+        tokio::spawn(async move { job_pool.run(batch).await });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    type SearchFilesStream = ReceiverStream<Result<SearchFilesResponse, Status>>;
+
+    async fn search_files(
+        &self,
+        request: TonicSearchFilesReq,
+    ) -> Result<TonicSearchFilesResp, Status> {
+        let user = get_user_or_return!(request);
+        let request = request.into_inner();
+        debug!("search_files(), user={}, request={:?}", user.name, request);
+        let full_path = resolve_path(&user.root, &request.dir_path, self.config.symlinks)
+            .map_err(get_error_status)?;
+        let filter = get_search_filter(&request)
+            .map_err(|e| Status::new(Code::InvalidArgument, e.to_string()))?;
+        let max_results = match request.max_results {
+            0 => DEFAULT_SEARCH_RESULTS,
+            max_results => max_results.min(MAX_SEARCH_RESULTS),
+        };
+
+        // walk in the pool, it stops when the client cancels and the receiver is dropped
+        let (sender, receiver) = mpsc::channel(SEARCH_CHANNEL_SIZE);
+        let root = user.root.clone();
+        let search = move || {
+            let mut count = 0;
+            let mut truncated = false;
+            let on_match = |path: &Path, metadata: &Metadata| {
+                if count >= max_results {
+                    truncated = true;
+                    return false;
+                }
+                let result = match get_search_result(&root, path, metadata) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        trace!("search_files(), skip {:?}, error={:?}", path, e);
+                        return true;
+                    }
+                };
+                count += 1;
+                let response = SearchFilesResponse {
+                    result: Some(result),
+                    truncated: false,
+                };
+                sender.blocking_send(Ok(response)).is_ok()
+            };
+            let result = search_files(&full_path, &filter, on_match, || sender.is_closed());
+            trace!("search_files(), count={}, truncated={}", count, truncated);
+            let last_message = match result {
+                Ok(_) if !truncated => return,
+                Ok(_) => Ok(SearchFilesResponse {
+                    result: None,
+                    truncated,
+                }),
+                Err(e) => Err(get_error_status(e)),
+            };
+            let _ = sender.blocking_send(last_message);
+        };
+        let stream_pool = self.stream_pool.clone();
+        tokio::spawn(async move {
+            stream_pool
+                .run(move || {
+                    search();
+                    Ok(())
+                })
+                .await
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    type SearchContentStream = ReceiverStream<Result<SearchContentResponse, Status>>;
+
+    async fn search_content(
+        &self,
+        request: TonicSearchContentReq,
+    ) -> Result<TonicSearchContentResp, Status> {
+        let user = get_user_or_return!(request);
+        let request = request.into_inner();
+        debug!(
+            "search_content(), user={}, request={:?}",
+            user.name, request
+        );
+        // matched lines are contents of files, so they are downloaded in fact
+        if !user.permission.download {
+            return Err(Status::new(Code::PermissionDenied, "Download not allowed"));
+        }
+        let content_index = match &self.content_index {
+            Some(content_index) => content_index.clone(),
+            None => {
+                return Err(Status::new(
+                    Code::FailedPrecondition,
+                    "Content index not enabled",
+                ))
+            }
+        };
+        let full_path = resolve_path(&user.root, &request.dir_path, self.config.symlinks)
+            .map_err(get_error_status)?;
+        let paths = content_index
+            .search(&full_path, &request.query)
+            .map_err(|e| Status::new(Code::InvalidArgument, e.to_string()))?;
+        let max_results = match request.max_results {
+            0 => DEFAULT_SEARCH_RESULTS,
+            max_results => max_results.min(MAX_SEARCH_RESULTS),
+        } as usize;
+        let max_lines = match request.max_lines_per_file {
+            0 => DEFAULT_MATCHED_LINES,
+            max_lines => max_lines.min(MAX_MATCHED_LINES),
+        } as usize;
+
+        // read matched lines in the pool, it stops when the client cancels
+        let (sender, receiver) = mpsc::channel(SEARCH_CHANNEL_SIZE);
+        let root = user.root.clone();
+        let search = move || {
+            let mut count = 0;
+            for path in paths {
+                if sender.is_closed() {
+                    break;
+                }
+                let response =
+                    match get_content_search_result(&root, &path, &request.query, max_lines) {
+                        Ok(Some(response)) => response,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            trace!("search_content(), skip {:?}, error={:?}", &path, e);
+                            continue;
+                        }
+                    };
+                if sender.blocking_send(Ok(response)).is_err() {
+                    break;
+                }
+                count += 1;
+                if count >= max_results {
+                    break;
+                }
+            }
+            trace!("search_content(), count={}", count);
+        };
+        let stream_pool = self.stream_pool.clone();
+        tokio::spawn(async move {
+            stream_pool
+                .run(move || {
+                    search();
+                    Ok(())
+                })
+                .await
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    type WatchDirStream = ReceiverStream<Result<WatchDirResponse, Status>>;
+
+    async fn watch_dir(&self, request: TonicWatchDirReq) -> Result<TonicWatchDirResp, Status> {
+        let user = get_user_or_return!(request);
+        let request = request.into_inner();
+        debug!("watch_dir(), user={}, request={:?}", user.name, request);
+        let slot = self
+            .watchers
+            .acquire(self.config.get_limited_owner(&user))
+            .ok_or_else(|| Status::new(Code::ResourceExhausted, "Too many watchers"))?;
+        let (change_sender, mut change_receiver) = mpsc::channel(WATCH_CHANNEL_SIZE);
+        let root = user.root.clone();
+        let symlinks = self.config.symlinks;
+        self.io_pool
+            .run(move || {
+                let full_path = resolve_path(&root, &request.dir_path, symlinks)?;
+                if !full_path.is_dir() {
+                    return Err(PathError::NotDir(full_path).into());
+                }
+                watch_dir(&full_path, request.recursive, change_sender, slot)
+            })
+            .await
+            .map_err(get_error_status)?;
+
+        // forward changes until the client cancels, then the watcher stops as receiver is dropped
+        let (sender, receiver) = mpsc::channel(WATCH_CHANNEL_SIZE);
+        let root = user.root.clone();
+        tokio::spawn(async move {
+            // client cancelling is noticed at once, even when nothing changes
+            while let Some(changes) = tokio::select! {
+                changes = change_receiver.recv() => changes,
+                _ = sender.closed() => None,
+            } {
+                let events: Vec<WatchEvent> = changes
+                    .into_iter()
+                    .filter_map(|change| get_watch_event(&root, change).ok())
+                    .collect();
+                if events.is_empty() {
+                    continue;
+                }
+                if sender.send(Ok(WatchDirResponse { events })).await.is_err() {
+                    break;
+                }
+            }
+            trace!("watch_dir(), stopped");
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn list_trash(&self, request: TonicListTrashReq) -> Result<TonicListTrashResp, Status> {
+        let user = get_user_or_return!(request);
+        if !user.permission.delete {
+            return Err(Status::new(Code::PermissionDenied, "Delete not allowed"));
+        }
+        debug!("list_trash(), user={}", user.name);
+        let items = self
+            .manage_pool
+            .run(move || Trash::new(&user.root).list())
+            .await
+            .map_err(get_error_status)?
+            .into_iter()
+            .map(|item| get_trash_item(item, self.config.trash_retention))
+            .collect();
+        Ok(Response::new(ListTrashResponse { items }))
+    }
+
+    async fn restore_from_trash(
+        &self,
+        request: TonicRestoreFromTrashReq,
+    ) -> Result<TonicRestoreFromTrashResp, Status> {
+        let user = get_user_or_return!(request);
+        if !user.permission.delete {
+            return Err(Status::new(Code::PermissionDenied, "Delete not allowed"));
+        }
+        let request = request.into_inner();
+        debug!(
+            "restore_from_trash(), user={}, request={:?}",
+            user.name, request
+        );
+        let on_conflict = get_conflict_policy(request.on_conflict());
+        let symlinks = self.config.symlinks;
+        let restored_path = self
+            .manage_pool
+            .run(move || {
+                match Trash::new(&user.root).restore(&request.id, symlinks, on_conflict)? {
+                    Some(path) => get_stripped_path_string(&user.root, &path),
+                    None => Ok(String::new()),
+                }
+            })
+            .await
+            .map_err(get_error_status)?;
+        Ok(Response::new(RestoreFromTrashResponse { restored_path }))
+    }
+
+    async fn empty_trash(
+        &self,
+        request: TonicEmptyTrashReq,
+    ) -> Result<TonicEmptyTrashResp, Status> {
+        let user = get_user_or_return!(request);
+        if !user.permission.delete {
+            return Err(Status::new(Code::PermissionDenied, "Delete not allowed"));
+        }
+        let request = request.into_inner();
+        debug!("empty_trash(), user={}, request={:?}", user.name, request);
+        let removed_count = self
+            .manage_pool
+            .run(move || Trash::new(&user.root).empty(&request.ids))
+            .await
+            .map_err(get_error_status)?;
+        Ok(Response::new(EmptyTrashResponse { removed_count }))
+    }
+
+    async fn submit_job(&self, request: TonicSubmitJobReq) -> Result<TonicSubmitJobResp, Status> {
+        let user = get_user_or_return!(request);
+        let request = request.into_inner();
+        debug!("submit_job(), user={}, request={:?}", user.name, request);
+        let (description, total_operations) = match &request.job {
+            Some(submit_job_request::Job::Manage(request)) => {
+                let description = match request.operations.as_slice() {
+                    [operation] => get_manage_description(operation),
+                    operations => format!("{} operations", operations.len()),
+                };
+                (description, request.operations.len() as u32)
+            }
+            Some(submit_job_request::Job::Archive(archive)) => {
+                if !user.permission.create {
+                    return Err(Status::new(Code::PermissionDenied, "Create not allowed"));
+                }
+                // archive contains contents of files, so they are downloaded in fact
+                if !user.permission.download {
+                    return Err(Status::new(Code::PermissionDenied, "Download not allowed"));
+                }
+                get_archive_format(&archive.format)
+                    .map_err(|e| Status::new(Code::InvalidArgument, e.to_string()))?;
+                let description = match archive.dir_path.is_empty() {
+                    true => "archive root".to_string(),
+                    false => format!("archive {}", archive.dir_path),
+                };
+                (description, 1)
+            }
+            None => return Err(Status::new(Code::InvalidArgument, "Job is required")),
+        };
+        let job = self
+            .jobs
+            .add(
+                &user.name,
+                self.config.get_limited_owner(&user).is_some(),
+                description,
+                total_operations,
+            )
+            .ok_or_else(|| Status::new(Code::ResourceExhausted, "Too many unfinished jobs"))?;
+        let id = job.get_info().id;
+
+        // run in the job pool, detached from this call so it survives the client going away
+        let symlinks = self.config.symlinks;
+        let job_pool = self.job_pool.clone();
+        tokio::spawn(async move {
+            let result = job_pool
+                .run(move || {
+                    match &request.job {
+                        Some(submit_job_request::Job::Manage(request)) => {
+                            run_manage_job(&job, &user, request, symlinks)
+                        }
+                        Some(submit_job_request::Job::Archive(archive)) => {
+                            run_archive_job(&job, &user, archive, symlinks)
+                        }
+                        None => {}
+                    }
+                    Ok(())
+                })
+                .await;
+            trace!("submit_job(), id={}, result={:?}", id, result);
+        });
+        Ok(Response::new(SubmitJobResponse { id }))
+    }
+
+    async fn list_jobs(&self, request: TonicListJobsReq) -> Result<TonicListJobsResp, Status> {
+        let user = get_user_or_return!(request);
+        debug!("list_jobs(), user={}", user.name);
+        let jobs = self
+            .jobs
+            .list(&user.name)
+            .into_iter()
+            .map(get_job)
+            .collect();
+        Ok(Response::new(ListJobsResponse { jobs }))
+    }
+
+    async fn get_job(&self, request: TonicGetJobReq) -> Result<TonicGetJobResp, Status> {
+        let user = get_user_or_return!(request);
+        let id = request.get_ref().id;
+        debug!("get_job(), user={}, id={}", user.name, id);
+        let job = self
+            .jobs
+            .get(&user.name, id)
+            .ok_or_else(|| Status::new(Code::NotFound, "Job not found"))?;
+        let job = Some(get_job(job.get_info()));
+        Ok(Response::new(GetJobResponse { job }))
+    }
+
+    async fn cancel_job(&self, request: TonicCancelJobReq) -> Result<TonicCancelJobResp, Status> {
+        let user = get_user_or_return!(request);
+        let id = request.get_ref().id;
+        debug!("cancel_job(), user={}, id={}", user.name, id);
+        self.jobs
+            .get(&user.name, id)
+            .ok_or_else(|| Status::new(Code::NotFound, "Job not found"))?
+            .cancel();
+        Ok(Response::new(CancelJobResponse {}))
+    }
+
+    type WatchJobStream = ReceiverStream<Result<WatchJobResponse, Status>>;
+
+    async fn watch_job(&self, request: TonicWatchJobReq) -> Result<TonicWatchJobResp, Status> {
+        let user = get_user_or_return!(request);
+        let id = request.get_ref().id;
+        debug!("watch_job(), user={}, id={}", user.name, id);
+        let mut info_receiver = self
+            .jobs
+            .get(&user.name, id)
+            .ok_or_else(|| Status::new(Code::NotFound, "Job not found"))?
+            .subscribe();
+
+        // send the job on changes, throttled like BatchManage, until it's finished or client cancels,
+        // cancelling is noticed while waiting too, so idle jobs never keep the task
+        let (sender, receiver) = mpsc::channel(JOB_CHANNEL_SIZE);
+        tokio::spawn(async move {
+            loop {
+                let info = info_receiver.borrow_and_update().clone();
+                let finished = info.state.is_finished();
+                let response = WatchJobResponse {
+                    job: Some(get_job(info)),
+                };
+                if sender.send(Ok(response)).await.is_err() || finished {
+                    break;
+                }
+                let changed = async {
+                    tokio::time::sleep(BATCH_PROGRESS_INTERVAL).await;
+                    info_receiver.changed().await.is_ok()
+                };
+                let changed = tokio::select! {
+                    changed = changed => changed,
+                    _ = sender.closed() => false,
+                };
+                if !changed {
+                    break;
+                }
+            }
+            trace!("watch_job(), id={}, stopped", id);
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
 }
 
 pub fn get_serva_manager(server_info: &ServerInfo) -> ServaManagerServerImpl {
     let config = Config::from(server_info);
-    ServaManagerServer::new(ServaManagerServiceImpl { config })
+    let content_index = match config.enable_index {
+        true => match ContentIndex::start(&config.root) {
+            Ok(content_index) => Some(content_index),
+            Err(e) => {
+                warn!(
+                    "Failed to start content index, content search disabled: {}",
+                    e
+                );
+                None
+            }
+        },
+        false => None,
+    };
+    if let Some(retention) = config.trash_retention {
+        let mut roots = vec![config.root.clone()];
+        roots.extend(
+            server_info
+                .users
+                .iter()
+                .map(|user| user.profile.root.clone()),
+        );
+        start_purging(roots, retention);
+    }
+    let upload_sessions = Arc::new(UploadSessionRegistry::default());
+    start_expiring_uploads(upload_sessions.clone(), server_info.io_pool.clone());
+    ServaManagerServer::new(ServaManagerServiceImpl {
+        config,
+        upload_sessions,
+        content_index,
+        jobs: Arc::new(JobRegistry::default()),
+        watchers: Arc::new(WatcherLimit::default()),
+        manage_pool: server_info.manage_pool.clone(),
+        io_pool: server_info.io_pool.clone(),
+        stream_pool: server_info.stream_pool.clone(),
+        job_pool: FsPool::new("job", server_info.arg_fs_workers),
+    })
 }
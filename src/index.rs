@@ -0,0 +1,286 @@
+use crate::{
+    search::{search_files, SearchFilter},
+    trash::is_in_trash,
+};
+use anyhow::anyhow;
+use log::{debug, trace};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::Metadata,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+type AnyError = anyhow::Error;
+
+// files larger than this are not treated as text, e.g. huge logs
+const MAX_INDEXED_FILE_SIZE: u64 = 8 * 1024 * 1024;
+// files with NUL in the head are binary
+const BINARY_CHECK_SIZE: usize = 8 * 1024;
+const MIN_TOKEN_LENGTH: usize = 2;
+const MAX_TOKEN_LENGTH: usize = 64;
+const MAX_SNIPPET_LENGTH: usize = 200;
+// changes within this interval are handled together
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Split text into lowercase alphanumeric tokens
+pub fn get_tokens(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| (MIN_TOKEN_LENGTH..=MAX_TOKEN_LENGTH).contains(&token.chars().count()))
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+fn read_text(path: &Path, metadata: &Metadata) -> Option<String> {
+    if metadata.len() > MAX_INDEXED_FILE_SIZE {
+        return None;
+    }
+    let mut data = vec![];
+    std::fs::File::open(path)
+        .ok()?
+        .read_to_end(&mut data)
+        .ok()?;
+    if data[..data.len().min(BINARY_CHECK_SIZE)].contains(&0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&data).to_string())
+}
+
+#[derive(Debug)]
+struct IndexedFile {
+    size: u64,
+    modified: Option<SystemTime>,
+    tokens: HashSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct IndexState {
+    files: HashMap<PathBuf, IndexedFile>,
+    postings: HashMap<String, HashSet<PathBuf>>,
+}
+
+impl IndexState {
+    fn is_indexed(&self, path: &Path, metadata: &Metadata) -> bool {
+        matches!(
+            self.files.get(path),
+            Some(file) if file.size == metadata.len() && file.modified == metadata.modified().ok()
+        )
+    }
+
+    fn insert(&mut self, path: &Path, metadata: &Metadata, tokens: HashSet<String>) {
+        self.remove(path);
+        for token in &tokens {
+            self.postings
+                .entry(token.clone())
+                .or_default()
+                .insert(path.to_path_buf());
+        }
+        let file = IndexedFile {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            tokens,
+        };
+        self.files.insert(path.to_path_buf(), file);
+    }
+
+    fn remove(&mut self, path: &Path) {
+        let file = match self.files.remove(path) {
+            Some(file) => file,
+            None => return,
+        };
+        for token in &file.tokens {
+            if let Some(paths) = self.postings.get_mut(token) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.postings.remove(token);
+                }
+            }
+        }
+    }
+
+    // remove the file, or all files under the dir
+    fn remove_all(&mut self, path: &Path) {
+        let paths: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|file_path| file_path.starts_with(path))
+            .cloned()
+            .collect();
+        for path in paths {
+            self.remove(&path);
+        }
+    }
+}
+
+/// Inverted index of text files under root, updated by watching file changes
+#[derive(Debug)]
+pub struct ContentIndex {
+    root: PathBuf,
+    state: RwLock<IndexState>,
+}
+
+impl ContentIndex {
+    /// Create index of root, files are indexed and watched in background
+    pub fn start(root: &Path) -> Result<Arc<Self>, AnyError> {
+        let index = Arc::new(ContentIndex {
+            root: root.to_path_buf(),
+            state: RwLock::new(IndexState::default()),
+        });
+        // start watching before walking, so no change is missed while walking
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        let background_index = index.clone();
+        thread::spawn(move || {
+            debug!(
+                "ContentIndex::start(), indexing {:?}",
+                &background_index.root
+            );
+            background_index.update(&background_index.root);
+            debug!(
+                "ContentIndex::start(), indexed {} files",
+                background_index.get_file_count()
+            );
+            background_index.handle_changes(watcher, receiver);
+        });
+        Ok(index)
+    }
+
+    pub fn get_file_count(&self) -> usize {
+        self.state
+            .read()
+            .map(|state| state.files.len())
+            .unwrap_or(0)
+    }
+
+    fn handle_changes(
+        &self,
+        _watcher: RecommendedWatcher,
+        receiver: mpsc::Receiver<notify::Result<notify::Event>>,
+    ) {
+        // collect changed paths until no more change in the interval, then update them
+        let mut changed_paths = HashSet::new();
+        loop {
+            let result = match changed_paths.is_empty() {
+                true => receiver
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                false => receiver.recv_timeout(DEBOUNCE_INTERVAL),
+            };
+            match result {
+                Ok(Ok(event)) if !event.kind.is_access() => changed_paths.extend(event.paths),
+                Ok(_) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    trace!("ContentIndex::handle_changes(), paths={:?}", &changed_paths);
+                    for path in changed_paths.drain() {
+                        self.update(&path);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    // reindex changed files under path, and remove deleted ones
+    fn update(&self, path: &Path) {
+        // files moved into trash are deleted ones
+        if is_in_trash(path) {
+            return;
+        }
+        if !path.exists() {
+            if let Ok(mut state) = self.state.write() {
+                state.remove_all(path);
+            }
+            return;
+        }
+        let filter = SearchFilter::default();
+        let update_file = |path: &Path, metadata: &Metadata| {
+            let is_indexed = self
+                .state
+                .read()
+                .map(|state| state.is_indexed(path, metadata))
+                .unwrap_or(false);
+            if is_indexed {
+                return true;
+            }
+            let tokens = read_text(path, metadata)
+                .map(|text| get_tokens(&text))
+                .unwrap_or_default();
+            if let Ok(mut state) = self.state.write() {
+                match tokens.is_empty() {
+                    true => state.remove(path),
+                    false => state.insert(path, metadata, tokens),
+                }
+            }
+            true
+        };
+        match path.symlink_metadata() {
+            Ok(metadata) if metadata.is_file() => {
+                update_file(path, &metadata);
+            }
+            Ok(metadata) if metadata.is_dir() => {
+                let _ = search_files(path, &filter, update_file, || false);
+            }
+            _ => {}
+        }
+    }
+
+    /// Get files under dir containing all tokens of query
+    pub fn search(&self, dir: &Path, query: &str) -> Result<Vec<PathBuf>, AnyError> {
+        let tokens = get_tokens(query);
+        if tokens.is_empty() {
+            return Err(anyhow!("query has no word to search"));
+        }
+        let state = self
+            .state
+            .read()
+            .map_err(|_| anyhow!("content index poisoned"))?;
+        let mut paths: Option<HashSet<&PathBuf>> = None;
+        for token in &tokens {
+            let token_paths: HashSet<&PathBuf> = match state.postings.get(token) {
+                Some(token_paths) => token_paths.iter().collect(),
+                None => return Ok(vec![]),
+            };
+            paths = Some(match paths {
+                Some(paths) => paths.intersection(&token_paths).cloned().collect(),
+                None => token_paths,
+            });
+        }
+        let mut paths: Vec<PathBuf> = paths
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| path.starts_with(dir))
+            .cloned()
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+/// Get lines containing all tokens of query, with 1-based line numbers
+pub fn get_matched_lines(
+    path: &Path,
+    query: &str,
+    max_lines: usize,
+) -> Result<Vec<(u64, String)>, AnyError> {
+    let tokens = get_tokens(query);
+    let reader = BufReader::new(std::fs::File::open(path)?);
+    let mut lines = vec![];
+    for (i, line) in reader.split(b'\n').enumerate() {
+        let line = String::from_utf8_lossy(&line?).to_string();
+        let line_tokens = get_tokens(&line);
+        if !tokens.iter().all(|token| line_tokens.contains(token)) {
+            continue;
+        }
+        let snippet: String = line.trim().chars().take(MAX_SNIPPET_LENGTH).collect();
+        lines.push((i as u64 + 1, snippet));
+        if lines.len() >= max_lines {
+            break;
+        }
+    }
+    Ok(lines)
+}
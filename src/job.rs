@@ -0,0 +1,237 @@
+use crate::manage::CopyProgress;
+use log::{debug, trace};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::watch;
+
+// finished jobs of one owner kept for listing, the oldest ones are dropped beyond it
+const MAX_FINISHED_JOBS_PER_OWNER: usize = 100;
+// queued and running jobs of all owners and of one owner, more are refused until some finish
+const MAX_UNFINISHED_JOBS: usize = 256;
+const MAX_UNFINISHED_JOBS_PER_OWNER: usize = 16;
+
+fn get_now_in_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+/// Result of one operation of job, code is the grpc status code
+#[derive(Debug, Clone)]
+pub struct JobResult {
+    pub index: u32,
+    pub code: i32,
+    pub message: String,
+}
+
+/// Snapshot of job, sent to watchers on every change
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: u64,
+    pub owner: String,
+    pub description: String,
+    pub state: JobState,
+    pub total_operations: u32,
+    /// Operation in progress, and its progress of copying or archiving
+    pub index: u32,
+    pub progress: CopyProgress,
+    pub results: Vec<JobResult>,
+    pub created_timestamp_in_ms: i64,
+    pub finished_timestamp_in_ms: i64,
+}
+
+/// Job running in background, it's not bound to the request submitting it
+#[derive(Debug)]
+pub struct Job {
+    info: watch::Sender<JobInfo>,
+    cancelled: AtomicBool,
+}
+
+impl Job {
+    pub fn get_info(&self) -> JobInfo {
+        self.info.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<JobInfo> {
+        self.info.subscribe()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Request cancelling, queued job is cancelled at once, running one stops at next check
+    pub fn cancel(&self) {
+        trace!("Job::cancel(), id={}", self.info.borrow().id);
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.info.send_if_modified(|info| match info.state {
+            JobState::Queued => {
+                info.state = JobState::Cancelled;
+                info.finished_timestamp_in_ms = get_now_in_ms();
+                true
+            }
+            _ => false,
+        });
+    }
+
+    /// Mark job running, false means it's cancelled before started
+    pub fn start(&self) -> bool {
+        self.info.send_if_modified(|info| match info.state {
+            JobState::Queued => {
+                info.state = JobState::Running;
+                true
+            }
+            _ => false,
+        })
+    }
+
+    pub fn set_progress(&self, index: u32, progress: &CopyProgress) {
+        self.info.send_modify(|info| {
+            info.index = index;
+            info.progress = progress.clone();
+        });
+    }
+
+    pub fn add_result(&self, result: JobResult) {
+        self.info.send_modify(|info| {
+            info.index = result.index;
+            info.results.push(result);
+        });
+    }
+
+    /// Finish job, it's failed when any operation failed, or cancelled when requested
+    pub fn finish(&self) {
+        self.info.send_modify(|info| {
+            info.state = match (self.is_cancelled(), &info.results) {
+                (true, _) => JobState::Cancelled,
+                (false, results) if results.iter().any(|result| result.code != 0) => {
+                    JobState::Failed
+                }
+                _ => JobState::Succeeded,
+            };
+            info.finished_timestamp_in_ms = get_now_in_ms();
+            debug!("Job::finish(), id={}, state={:?}", info.id, info.state);
+        });
+    }
+}
+
+/// Jobs of all users in memory, they are lost when the server stops
+#[derive(Debug, Default)]
+pub struct JobRegistry {
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+}
+
+impl JobRegistry {
+    /// Add job of owner, None when there are too many unfinished jobs.
+    /// Unfinished jobs are limited per owner only when limit_owner is true.
+    pub fn add(
+        &self,
+        owner: &str,
+        limit_owner: bool,
+        description: String,
+        total_operations: u32,
+    ) -> Option<Arc<Job>> {
+        let mut jobs = self.jobs.lock().ok()?;
+        let (mut unfinished, mut owned) = (0, 0);
+        for info in jobs.values().map(|job| job.info.borrow()) {
+            if !info.state.is_finished() {
+                unfinished += 1;
+                owned += usize::from(info.owner == owner);
+            }
+        }
+        if unfinished >= MAX_UNFINISHED_JOBS
+            || (limit_owner && owned >= MAX_UNFINISHED_JOBS_PER_OWNER)
+        {
+            debug!(
+                "JobRegistry::add(), too many jobs, owner={}, owned={}, unfinished={}",
+                owner, owned, unfinished
+            );
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let info = JobInfo {
+            id,
+            owner: owner.to_string(),
+            description,
+            state: JobState::Queued,
+            total_operations,
+            index: 0,
+            progress: CopyProgress::default(),
+            results: vec![],
+            created_timestamp_in_ms: get_now_in_ms(),
+            finished_timestamp_in_ms: 0,
+        };
+        trace!("JobRegistry::add(), info={:?}", &info);
+        let (sender, _) = watch::channel(info);
+        let job = Arc::new(Job {
+            info: sender,
+            cancelled: AtomicBool::new(false),
+        });
+        jobs.insert(id, job.clone());
+        Self::drop_finished(&mut jobs, owner);
+        Some(job)
+    }
+
+    // finished jobs are dropped per owner, so others never push out jobs of an owner
+    fn drop_finished(jobs: &mut BTreeMap<u64, Arc<Job>>, owner: &str) {
+        let finished: Vec<u64> = jobs
+            .iter()
+            .filter(|(_, job)| {
+                let info = job.info.borrow();
+                info.owner == owner && info.state.is_finished()
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        let count = finished.len().saturating_sub(MAX_FINISHED_JOBS_PER_OWNER);
+        for id in &finished[..count] {
+            jobs.remove(id);
+        }
+    }
+
+    /// Get job of owner, jobs of others are never exposed
+    pub fn get(&self, owner: &str, id: u64) -> Option<Arc<Job>> {
+        let jobs = self.jobs.lock().ok()?;
+        jobs.get(&id)
+            .filter(|job| job.info.borrow().owner == owner)
+            .cloned()
+    }
+
+    /// List jobs of owner, newest first
+    pub fn list(&self, owner: &str) -> Vec<JobInfo> {
+        let jobs = match self.jobs.lock() {
+            Ok(jobs) => jobs,
+            Err(_) => return vec![],
+        };
+        jobs.values()
+            .rev()
+            .map(|job| job.get_info())
+            .filter(|info| info.owner == owner)
+            .collect()
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{
+    resolve::{get_symlink_target, SymlinkPolicy},
+    trash::TRASH_DIR_NAME,
+};
+use anyhow::anyhow;
+use log::trace;
+use regex::Regex;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+type AnyError = anyhow::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Name,
+    Size,
+    Modified,
+}
+
+/// Options of listing a page of dir, dirs are always listed before files
+#[derive(Debug)]
+pub struct ListOptions {
+    pub sort_by: SortBy,
+    pub descending: bool,
+    pub name_filter: Option<Regex>,
+    pub show_hidden: bool,
+    pub page_size: usize,
+    pub page_token: Option<SortKey>,
+    pub symlinks: SymlinkPolicy,
+}
+
+/// Entry of dir, metadata is of the target when symlink is followed
+#[derive(Debug)]
+pub struct ListEntry {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+    pub symlink_metadata: Metadata,
+}
+
+#[derive(Debug, Default)]
+pub struct ListPage {
+    pub entries: Vec<ListEntry>,
+    pub next_page_token: String,
+    pub total_dirs: u64,
+    pub total_files: u64,
+}
+
+/// Position of entry in the sorted list, the page token is the key of the last entry
+#[derive(Debug, PartialEq, Eq)]
+pub struct SortKey {
+    is_file: bool,
+    value: i128,
+    name: String,
+}
+
+impl SortKey {
+    fn new(sort_by: SortBy, name: &str, metadata: &Metadata) -> Self {
+        let value = match sort_by {
+            SortBy::Name => 0,
+            // dirs have no size, so they are sorted by name
+            SortBy::Size if metadata.is_file() => metadata.len() as i128,
+            SortBy::Size => 0,
+            SortBy::Modified => metadata
+                .modified()
+                .ok()
+                .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|mtime| mtime.as_nanos() as i128)
+                .unwrap_or_default(),
+        };
+        SortKey {
+            is_file: !metadata.is_dir(),
+            value,
+            name: name.to_string(),
+        }
+    }
+
+    fn compare(&self, other: &Self, descending: bool) -> Ordering {
+        let ordering = self.value.cmp(&other.value).then_with(|| {
+            // names are compared case insensitively, and case decides only when they are equal
+            self.name
+                .to_lowercase()
+                .cmp(&other.name.to_lowercase())
+                .then_with(|| self.name.cmp(&other.name))
+        });
+        let ordering = match descending {
+            true => ordering.reverse(),
+            false => ordering,
+        };
+        self.is_file.cmp(&other.is_file).then(ordering)
+    }
+
+    fn to_token(&self, options: &ListOptions) -> String {
+        let token = format!(
+            "{:?}:{}:{}:{}:{}",
+            options.sort_by, options.descending as u8, self.is_file as u8, self.value, self.name
+        );
+        base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Parse page token, sort options are checked, so it's rejected when they are changed
+    pub fn from_token(token: &str, sort_by: SortBy, descending: bool) -> Result<Self, AnyError> {
+        let invalid_token = || anyhow!("invalid page token");
+        let token = base64::decode_config(token, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|token| String::from_utf8(token).ok())
+            .ok_or_else(invalid_token)?;
+        let mut fields = token.splitn(5, ':');
+        let mut next_field = || fields.next().ok_or_else(invalid_token);
+        let (token_sort_by, token_descending) = (next_field()?, next_field()?);
+        if token_sort_by != format!("{:?}", sort_by)
+            || token_descending != (descending as u8).to_string()
+        {
+            return Err(anyhow!("page token is for different sort options"));
+        }
+        let is_file = next_field()? == "1";
+        let value = next_field()?.parse::<i128>().map_err(|_| invalid_token())?;
+        let name = next_field()?.to_string();
+        Ok(SortKey {
+            is_file,
+            value,
+            name,
+        })
+    }
+}
+
+/// Whether the entry is hidden, as dot files on unix
+pub fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+// symlinks are followed by policy, only when the target is a dir or file
+fn get_followed_metadata(path: &Path, root: &Path, symlinks: SymlinkPolicy) -> Option<Metadata> {
+    get_symlink_target(root, path, symlinks)?
+        .metadata()
+        .ok()
+        .filter(|metadata| metadata.is_dir() || metadata.is_file())
+}
+
+// entry waiting for the page, ordered by its key, so the heap keeps the first ones of the page
+struct PageEntry {
+    key: SortKey,
+    descending: bool,
+    path: PathBuf,
+    metadata: Metadata,
+    symlink_metadata: Metadata,
+}
+
+impl Ord for PageEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.compare(&other.key, self.descending)
+    }
+}
+
+impl PartialOrd for PageEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PageEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PageEntry {}
+
+/// List a page of dir, entries which are not dirs are counted as files, e.g. symlinks and sockets.
+/// The next page starts after the last entry of this page, so it's stable when entries change.
+/// Entries failed to get metadata are skipped, e.g. removed while listing.
+pub fn list_dir_page(dir: &Path, root: &Path, options: &ListOptions) -> Result<ListPage, AnyError> {
+    let mut page = ListPage::default();
+    // one more entry than page size is kept to know whether there is a next page
+    let mut entries = BinaryHeap::with_capacity(options.page_size + 2);
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let symlink_metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                trace!("list_dir_page(), skip {:?}, error={:?}", entry.path(), e);
+                continue;
+            }
+        };
+        let metadata = match symlink_metadata.is_symlink() {
+            true => get_followed_metadata(&entry.path(), root, options.symlinks)
+                .unwrap_or_else(|| symlink_metadata.clone()),
+            false => symlink_metadata.clone(),
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == TRASH_DIR_NAME || !options.show_hidden && is_hidden(&name) {
+            continue;
+        }
+        if matches!(&options.name_filter, Some(regex) if !regex.is_match(&name)) {
+            continue;
+        }
+        match metadata.is_dir() {
+            true => page.total_dirs += 1,
+            false => page.total_files += 1,
+        }
+        let key = SortKey::new(options.sort_by, &name, &metadata);
+        if matches!(&options.page_token, Some(last_key) if key.compare(last_key, options.descending) != Ordering::Greater)
+        {
+            continue;
+        }
+        entries.push(PageEntry {
+            key,
+            descending: options.descending,
+            path: entry.path(),
+            metadata,
+            symlink_metadata,
+        });
+        if entries.len() > options.page_size + 1 {
+            entries.pop();
+        }
+    }
+    let mut entries = entries.into_sorted_vec();
+    if entries.len() > options.page_size {
+        entries.truncate(options.page_size);
+        if let Some(entry) = entries.last() {
+            page.next_page_token = entry.key.to_token(options);
+        }
+    }
+    trace!(
+        "list_dir_page(), dir={:?}, count={}, next_page_token={}",
+        dir,
+        entries.len(),
+        &page.next_page_token
+    );
+    page.entries = entries
+        .into_iter()
+        .map(|entry| ListEntry {
+            path: entry.path,
+            metadata: entry.metadata,
+            symlink_metadata: entry.symlink_metadata,
+        })
+        .collect();
+    Ok(page)
+}
@@ -1,14 +1,45 @@
+use auth::{Auth, AuthService};
+use axum::{body::BoxBody, http::StatusCode, routing::get_service, Router};
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
 use data::ServerInfo;
+use hyper::{Body, Request, Response};
+use log::error;
 use multiplex::MultiplexService;
-use std::net::{IpAddr, SocketAddr};
-use tower::make::Shared;
+use pool::{IO_WORKERS, STREAM_WORKERS};
+use resolve::SymlinkPolicy;
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+use tower::{make::Shared, Service};
 use tower_http::services::ServeDir;
 
+type AnyError = anyhow::Error;
+
+mod archive;
+mod auth;
+mod conditional;
+mod conflict;
 mod data;
 mod grpc;
+mod index;
+mod job;
+mod listing;
+mod manage;
 mod multiplex;
+mod pool;
+mod range;
+mod resolve;
+mod search;
 mod serve;
+mod thumbnail;
+mod tls;
+mod trash;
+mod upload;
+mod user;
+mod watch;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -30,6 +61,81 @@ struct Args {
     disable_upload: bool,
     #[clap(long, value_parser)]
     disable_download: bool,
+    /// Require http basic auth in format user:password
+    #[clap(long, value_parser)]
+    auth: Option<String>,
+    /// Require token by "Authorization: Bearer <token>" or query "?token=<token>"
+    #[clap(long, value_parser)]
+    token: Option<String>,
+    /// Json or toml (by extension) file of users with their own credentials, permission and root
+    #[clap(long, value_parser)]
+    users: Option<String>,
+    /// Mime types displayed in browser instead of downloaded, e.g. image/*,video/*,application/pdf
+    #[clap(long, value_parser)]
+    inline_types: Option<String>,
+    /// Index text files under dir for content search, and update it on file changes
+    #[clap(long, value_parser)]
+    enable_index: bool,
+    /// How symlinks are followed, within-root follows symlinks to targets inside root only
+    #[clap(long, value_enum, default_value = "within-root")]
+    symlinks: SymlinkPolicy,
+    /// Days to keep deleted items in trash before purged, 0 keeps them until trash is emptied
+    #[clap(long, value_parser, default_value_t = 30)]
+    trash_retention_days: u64,
+    /// Workers running file management like copying and deleting, others wait for a free one
+    #[clap(long, value_parser, default_value_t = 4)]
+    fs_workers: usize,
+    /// Workers running quick filesystem calls like listing, uploading and thumbnails
+    #[clap(long, value_parser, default_value_t = IO_WORKERS)]
+    io_workers: usize,
+    /// Workers running long streams like archive downloads and searches
+    #[clap(long, value_parser, default_value_t = STREAM_WORKERS)]
+    stream_workers: usize,
+    /// Serve https with certificate file in pem format
+    #[clap(long, value_parser, requires = "tls-key")]
+    tls_cert: Option<String>,
+    /// Private key file of --tls-cert in pem format
+    #[clap(long, value_parser, requires = "tls-cert")]
+    tls_key: Option<String>,
+    /// Serve https with a cached self-signed certificate covering all listening ip
+    #[clap(long, value_parser, conflicts_with = "tls-cert")]
+    tls_self_signed: bool,
+    /// Dir caching the self-signed certificate, default is $XDG_CONFIG_HOME/serva
+    #[clap(long, value_parser)]
+    config_dir: Option<String>,
+}
+
+async fn get_tls_config(
+    args: &Args,
+    server_info: &ServerInfo,
+) -> Result<Option<RustlsConfig>, AnyError> {
+    let (cert, key) = match (&args.tls_cert, &args.tls_key, args.tls_self_signed) {
+        (Some(cert), Some(key), _) => (cert.into(), key.into()),
+        (_, _, true) => tls::get_self_signed_files(
+            args.config_dir.as_deref().map(Path::new),
+            &server_info.available_ip,
+        )?,
+        _ => return Ok(None),
+    };
+    println!("TLS certificate: {:?}", cert);
+    println!("TLS fingerprint(sha256): {}", tls::get_fingerprint(&cert)?);
+    Ok(Some(RustlsConfig::from_pem_file(cert, key).await?))
+}
+
+async fn run<S>(addr: SocketAddr, tls_config: Option<RustlsConfig>, service: S)
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>,
+    S: Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let make_service = Shared::new(service);
+    match tls_config {
+        Some(tls_config) => axum_server::bind_rustls(addr, tls_config)
+            .serve(make_service)
+            .await
+            .unwrap(),
+        None => axum::Server::bind(&addr).serve(make_service).await.unwrap(),
+    }
 }
 
 #[tokio::main]
@@ -50,22 +156,57 @@ async fn main() {
         !args.disable_download,
         args.ip,
         args.port,
+        args.auth.clone(),
+        args.token.clone(),
+        args.users.clone(),
+        args.inline_types.clone(),
+        args.enable_index,
+        args.symlinks,
+        args.trash_retention_days,
+        args.fs_workers,
+        args.io_workers,
+        args.stream_workers,
     )
     .unwrap();
     println!("Server Info:\n{}", server_info);
     let addr = SocketAddr::from((server_info.arg_ip, server_info.arg_port));
+    let tls_config = match get_tls_config(&args, &server_info).await {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            error!("Failed to load TLS certificate: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
     for ip in &server_info.available_ip {
-        println!("listening on {}:{}", ip, server_info.arg_port);
+        let host = match ip {
+            IpAddr::V4(_) => ip.to_string(),
+            IpAddr::V6(_) => format!("[{}]", ip),
+        };
+        println!(
+            "listening on {}://{}:{}",
+            scheme, host, server_info.arg_port
+        );
     }
 
     // serve mode
     if args.serve_mode {
         println!("Serving files under {} only", &server_info.root_canonical);
-        let service = ServeDir::new(&args.dir);
-        axum::Server::bind(&addr)
-            .serve(Shared::new(service))
-            .await
-            .unwrap();
+        let service =
+            get_service(ServeDir::new(&args.dir)).handle_error(|e: std::io::Error| async move {
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            });
+        let service = AuthService::new(
+            Router::new().fallback(service),
+            Auth::from(&server_info)
+                .protect_all()
+                .secure_cookie(tls_config.is_some()),
+        );
+        run(addr, tls_config, service).await;
         return;
     }
 
@@ -73,8 +214,11 @@ async fn main() {
     let file_serve_service = serve::get_serve_file_service(&server_info);
     let grpc_service = tonic_web::enable(grpc::get_serva_manager(&server_info));
     let multiplex_service = MultiplexService::new(file_serve_service, grpc_service);
-    let make_service = Shared::new(multiplex_service);
+    let auth_service = AuthService::new(
+        multiplex_service,
+        Auth::from(&server_info).secure_cookie(tls_config.is_some()),
+    );
 
     // run it
-    axum::Server::bind(&addr).serve(make_service).await.unwrap();
+    run(addr, tls_config, auth_service).await;
 }
@@ -0,0 +1,525 @@
+use crate::{
+    archive::{get_archive_items, get_archive_name, save_archive, ArchiveFormat},
+    conflict::{get_temp_path, remove_existing, resolve_conflict, ConflictPolicy},
+    resolve::{
+        check_not_in_trash, resolve_entry_path, resolve_new_path, resolve_path, validate_name,
+        SymlinkPolicy,
+    },
+    trash::{is_in_trash, Trash},
+    upload::{get_sha256_of_file, verify_hash},
+};
+use anyhow::anyhow;
+use filetime::FileTime;
+use log::{debug, trace};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, Metadata},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+type AnyError = anyhow::Error;
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+// existing targets are replaced only when done, they are written into temp with random name
+// beside them, which is created exclusively and removed only by the operation creating it
+const ARCHIVE_FILE_SUFFIX: &str = "archiving";
+const COPY_FILE_SUFFIX: &str = "copying";
+const MOVE_FILE_SUFFIX: &str = "moving";
+const CREATE_FILE_SUFFIX: &str = "creating";
+
+#[derive(Debug, Clone, Copy)]
+pub struct ManageOptions {
+    pub symlinks: SymlinkPolicy,
+    pub on_conflict: ConflictPolicy,
+}
+
+// how entries are copied, copying for moving keeps more metadata and verifies data,
+// since the source is removed afterwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyMode {
+    Copy,
+    Move,
+}
+
+/// Progress of copying, totals are counted before copying starts
+#[derive(Debug, Clone, Default)]
+pub struct CopyProgress {
+    pub total_files: u64,
+    pub copied_files: u64,
+    pub total_bytes: u64,
+    pub copied_bytes: u64,
+}
+
+/// Called after every copied buffer and file, copying is cancelled when it returns false
+pub type ProgressHandler<'a> = dyn FnMut(&CopyProgress) -> bool + 'a;
+
+/// Error of cancelled operation, it's an io error so it's mapped like others
+pub fn get_cancelled_error() -> AnyError {
+    io::Error::new(io::ErrorKind::Interrupted, "cancelled").into()
+}
+
+// error of invalid operation, like copying dir into itself
+fn get_invalid_input_error(message: &str) -> AnyError {
+    io::Error::new(io::ErrorKind::InvalidInput, message).into()
+}
+
+// whether both paths are the same existing file after following symlinks
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+// whether both paths are the same entry without following symlinks, e.g. names differing
+// in case only on case-insensitive filesystems
+fn is_same_entry(a: &Path, b: &Path) -> bool {
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::MetadataExt;
+        matches!((a.symlink_metadata(), b.symlink_metadata()),
+            (Ok(a), Ok(b)) if a.dev() == b.dev() && a.ino() == b.ino())
+    }
+    #[cfg(target_os = "windows")]
+    is_same_file(a, b)
+}
+
+fn is_existing(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+fn is_crosses_devices_error(e: &AnyError) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::CrossesDevices)
+}
+
+fn is_cancelled_error(e: &AnyError) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::Interrupted)
+}
+
+// add path to error of copying or removing an entry, so partial failures are reported precisely
+fn get_entry_error(e: AnyError, action: &str, path: &Path) -> AnyError {
+    if is_cancelled_error(&e) {
+        return e;
+    }
+    let message = format!("failed to {} {:?}: {}", action, path, e);
+    e.context(message)
+}
+
+// access and modification times are kept when moving, symlinks themselves included
+fn set_times(path: &Path, metadata: &Metadata) -> Result<(), AnyError> {
+    let atime = FileTime::from_last_access_time(metadata);
+    let mtime = FileTime::from_last_modification_time(metadata);
+    match metadata.is_symlink() {
+        true => filetime::set_symlink_file_times(path, atime, mtime)?,
+        false => filetime::set_file_times(path, atime, mtime)?,
+    }
+    Ok(())
+}
+
+fn get_file_name(path: &Path) -> Result<String, AnyError> {
+    let file_name = path.file_name().ok_or_else(|| anyhow!("no file name"))?;
+    Ok(file_name.to_string_lossy().to_string())
+}
+
+// error of entries other than dirs, files and symlinks, like fifos, sockets and devices,
+// they are skipped when copying, but could not be moved since the source is removed
+fn get_unsupported_error(path: &Path) -> AnyError {
+    let message = format!(
+        "{:?} is not a file, dir or symlink, it could not be moved",
+        path
+    );
+    io::Error::new(io::ErrorKind::Unsupported, message).into()
+}
+
+// count files and bytes to copy, other entries are skipped, or refused when moving
+fn count_tree(path: &Path, mode: CopyMode, progress: &mut CopyProgress) -> Result<(), AnyError> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            count_tree(&entry?.path(), mode, progress)?;
+        }
+    } else if metadata.is_file() || metadata.is_symlink() {
+        progress.total_files += 1;
+        if metadata.is_file() {
+            progress.total_bytes += metadata.len();
+        }
+    } else if mode == CopyMode::Move {
+        return Err(get_unsupported_error(path));
+    }
+    Ok(())
+}
+
+// symlinks in copied dirs are copied as they are, never followed
+fn copy_symlink(from: &Path, to: &Path) -> Result<(), AnyError> {
+    let target = std::fs::read_link(from)?;
+    #[cfg(not(target_os = "windows"))]
+    std::os::unix::fs::symlink(target, to)?;
+    #[cfg(target_os = "windows")]
+    match from.is_dir() {
+        true => std::os::windows::fs::symlink_dir(target, to)?,
+        false => std::os::windows::fs::symlink_file(target, to)?,
+    }
+    Ok(())
+}
+
+fn copy_file_data(
+    from: &Path,
+    to: &Path,
+    mode: CopyMode,
+    progress: &mut CopyProgress,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    let mut reader = File::open(from)?;
+    let mut writer = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let size = reader.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..size])?;
+        if mode == CopyMode::Move {
+            hasher.update(&buffer[..size]);
+        }
+        progress.copied_bytes += size as u64;
+        if !on_progress(progress) {
+            return Err(get_cancelled_error());
+        }
+    }
+    // permissions are kept as std::fs::copy does
+    let metadata = reader.metadata()?;
+    writer.set_permissions(metadata.permissions())?;
+    if mode == CopyMode::Move {
+        // data is on disk and read back the same before the source is removed
+        writer.sync_all()?;
+        drop(writer);
+        let expected = format!("{:x}", hasher.finalize());
+        verify_hash("file", &expected, || get_sha256_of_file(to))?;
+        set_times(to, &metadata)?;
+    }
+    Ok(())
+}
+
+fn add_copied_file(
+    progress: &mut CopyProgress,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    progress.copied_files += 1;
+    match on_progress(progress) {
+        true => Ok(()),
+        false => Err(get_cancelled_error()),
+    }
+}
+
+fn copy_tree(
+    from: &Path,
+    to: &Path,
+    mode: CopyMode,
+    progress: &mut CopyProgress,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    let metadata = from.symlink_metadata()?;
+    if metadata.is_dir() {
+        std::fs::create_dir(to).map_err(|e| get_entry_error(e.into(), "create", to))?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(
+                &entry.path(),
+                &to.join(entry.file_name()),
+                mode,
+                progress,
+                on_progress,
+            )?;
+        }
+        // set after its entries are created, which change the time and may need the permission
+        if mode == CopyMode::Move {
+            std::fs::set_permissions(to, metadata.permissions())
+                .map_err(AnyError::from)
+                .and_then(|_| set_times(to, &metadata))
+                .map_err(|e| get_entry_error(e, "keep metadata of", to))?;
+        }
+        return Ok(());
+    } else if metadata.is_symlink() {
+        copy_symlink(from, to)
+            .and_then(|_| match mode {
+                CopyMode::Move => set_times(to, &metadata),
+                CopyMode::Copy => Ok(()),
+            })
+            .map_err(|e| get_entry_error(e, "copy", from))?;
+    } else if metadata.is_file() {
+        copy_file_data(from, to, mode, progress, on_progress)
+            .map_err(|e| get_entry_error(e, "copy", from))?;
+    } else if mode == CopyMode::Move {
+        // refused by count_tree already, unless created after counting
+        return Err(get_unsupported_error(from));
+    } else {
+        trace!("copy_tree(), skip {:?}", from);
+        return Ok(());
+    }
+    add_copied_file(progress, on_progress)
+}
+
+// remove entries one by one like remove_dir_all, but the entry failed to remove is reported
+fn remove_tree(path: &Path) -> Result<(), AnyError> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            remove_tree(&entry?.path())?;
+        }
+        std::fs::remove_dir(path).map_err(|e| get_entry_error(e.into(), "remove", path))?;
+    } else {
+        std::fs::remove_file(path).map_err(|e| get_entry_error(e.into(), "remove", path))?;
+    }
+    Ok(())
+}
+
+// move entry to another filesystem by copying, verifying and then removing the source,
+// source is kept when copying fails, and target is kept when removing source fails
+fn move_across_filesystems(
+    from: &Path,
+    to: &Path,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    debug!("move_across_filesystems(), from={:?}, to={:?}", from, to);
+    let mut progress = CopyProgress::default();
+    // unsupported entries are refused before anything is copied
+    count_tree(from, CopyMode::Move, &mut progress)?;
+    if let Err(e) = copy_tree(from, to, CopyMode::Move, &mut progress, on_progress) {
+        let _ = remove_existing(to);
+        if is_cancelled_error(&e) {
+            return Err(e);
+        }
+        let message = format!("{}, nothing is moved", e);
+        return Err(e.context(message));
+    }
+    remove_tree(from).map_err(|e| {
+        let message = format!("{}, copied to {:?} but source is partially kept", e, to);
+        e.context(message)
+    })
+}
+
+pub fn create_dir(
+    root: &Path,
+    dir_path: &str,
+    dir_name: &str,
+    options: &ManageOptions,
+) -> Result<(), AnyError> {
+    let target = resolve_new_path(root, dir_path, dir_name, options.symlinks)?;
+    // existing dir is kept when overwriting, so creating the same dir again succeeds
+    if options.on_conflict == ConflictPolicy::Overwrite && target.is_dir() {
+        return Ok(());
+    }
+    let target = match resolve_conflict(&target, options.on_conflict)? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+    trace!("create_dir(), target={:?}", target);
+    if !is_existing(&target) {
+        std::fs::create_dir(target)?;
+        return Ok(());
+    }
+    // existing entry is moved into trash only when the dir is created
+    let temp = get_temp_path(&target, CREATE_FILE_SUFFIX)?;
+    let result = std::fs::create_dir(&temp)
+        .map_err(AnyError::from)
+        .and_then(|_| Trash::new(root).replace(&temp, &target));
+    if result.is_err() {
+        let _ = remove_existing(&temp);
+    }
+    result
+}
+
+/// Copy file or dir into dir, partially copied target is removed when failed or cancelled
+pub fn copy_file(
+    root: &Path,
+    file_path_name: &str,
+    dir_path: &str,
+    options: &ManageOptions,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    let from = resolve_path(root, file_path_name, options.symlinks)?;
+    let file_name = get_file_name(&from)?;
+    let to = resolve_path(root, dir_path, options.symlinks)?.join(file_name);
+    check_not_in_trash(root, &to)?;
+    let to = match resolve_conflict(&to, options.on_conflict)? {
+        Some(to) => to,
+        None => return Ok(()),
+    };
+    // overwriting itself is a no-op, and it must not be removed before copying
+    if is_same_file(&from, &to) {
+        return Ok(());
+    }
+    if from.starts_with(&to) {
+        return Err(get_invalid_input_error("target contains source"));
+    }
+    if from.is_dir() && to.starts_with(&from) {
+        return Err(get_invalid_input_error(
+            "dir could not be copied into itself",
+        ));
+    }
+    trace!("copy_file(), from={:?}, to={:?}", &from, &to);
+    let mut progress = CopyProgress::default();
+    match from.is_dir() {
+        true => count_tree(&from, CopyMode::Copy, &mut progress)?,
+        false => {
+            progress.total_files = 1;
+            progress.total_bytes = from.metadata()?.len();
+        }
+    }
+    // existing target is moved into trash only when copied, so it's kept when copying fails
+    let overwriting = is_existing(&to);
+    let temp = match overwriting {
+        true => get_temp_path(&to, COPY_FILE_SUFFIX)?,
+        false => to.clone(),
+    };
+    let result = match from.is_dir() {
+        true => copy_tree(&from, &temp, CopyMode::Copy, &mut progress, on_progress),
+        // source symlink is followed, so its target is copied
+        false => copy_file_data(&from, &temp, CopyMode::Copy, &mut progress, on_progress)
+            .and_then(|_| add_copied_file(&mut progress, on_progress)),
+    }
+    .and_then(|_| match overwriting {
+        true => Trash::new(root).replace(&temp, &to),
+        false => Ok(()),
+    });
+    if result.is_err() {
+        let _ = remove_existing(&temp);
+    }
+    result
+}
+
+/// Move entry into trash, root and trash itself could not be deleted
+pub fn delete_file(
+    root: &Path,
+    file_path_name: &str,
+    symlinks: SymlinkPolicy,
+) -> Result<(), AnyError> {
+    // symlinks are deleted themselves, never their targets
+    let full_name = resolve_entry_path(root, file_path_name, symlinks)?;
+    trace!("delete_file(), full_name={:?}", &full_name);
+    if full_name == root || is_in_trash(full_name.strip_prefix(root)?) {
+        return Err(get_invalid_input_error(
+            "root or trash could not be deleted",
+        ));
+    }
+    let id = Trash::new(root).move_to_trash(&full_name)?;
+    debug!("delete_file(), moved to trash, id={}", id);
+    Ok(())
+}
+
+/// Move entry by renaming to target which doesn't exist. It's copied, verified and then removed
+/// when moved to another filesystem, e.g. to a disk mounted under root.
+pub fn move_entry(
+    from: &Path,
+    to: &Path,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    match std::fs::rename(from, to).map_err(AnyError::from) {
+        // rename works within one filesystem only
+        Err(e) if is_crosses_devices_error(&e) => move_across_filesystems(from, to, on_progress),
+        result => result,
+    }
+}
+
+// rename entry itself by conflict policy, shared by moving and renaming
+fn rename_entry(
+    root: &Path,
+    from: &Path,
+    to: &Path,
+    on_conflict: ConflictPolicy,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    if from == to {
+        return Ok(());
+    }
+    // target is joined with name of source or new name, it's never resolved
+    check_not_in_trash(root, to)?;
+    let to = match resolve_conflict(to, on_conflict)? {
+        Some(to) => to,
+        None => return Ok(()),
+    };
+    trace!("rename_entry(), from={:?}, to={:?}", from, &to);
+    if from.starts_with(&to) {
+        return Err(get_invalid_input_error("target contains source"));
+    }
+    // same entry under another name is renamed only, nothing is removed
+    if is_same_entry(from, &to) {
+        std::fs::rename(from, &to)?;
+        return Ok(());
+    }
+    if !is_existing(&to) {
+        return move_entry(from, &to, on_progress);
+    }
+    // existing target is moved into trash only when source is moved beside it,
+    // and source is moved back when replacing fails
+    let temp = get_temp_path(&to, MOVE_FILE_SUFFIX)?;
+    move_entry(from, &temp, on_progress)?;
+    Trash::new(root).replace(&temp, &to).inspect_err(|_| {
+        let _ = move_entry(&temp, from, &mut |_| true);
+    })
+}
+
+/// Move file or dir into dir, it's copied and then removed when moved to another filesystem
+pub fn move_file(
+    root: &Path,
+    file_path_name: &str,
+    dir_path: &str,
+    options: &ManageOptions,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    let from = resolve_entry_path(root, file_path_name, options.symlinks)?;
+    let file_name = get_file_name(&from)?;
+    let to = resolve_path(root, dir_path, options.symlinks)?.join(file_name);
+    trace!("move_file(), from={:?}, to={:?}", &from, &to);
+    rename_entry(root, &from, &to, options.on_conflict, on_progress)
+}
+
+pub fn rename_file(
+    root: &Path,
+    file_path_name: &str,
+    new_name: &str,
+    options: &ManageOptions,
+) -> Result<(), AnyError> {
+    let from = resolve_entry_path(root, file_path_name, options.symlinks)?;
+    validate_name(new_name)?;
+    let to = from.with_file_name(new_name);
+    trace!("rename_file(), from={:?}, to={:?}", &from, &to);
+    rename_entry(root, &from, &to, options.on_conflict, &mut |_| true)
+}
+
+/// Save archive of paths in dir into the dir, named after the dir when target is empty
+#[allow(clippy::too_many_arguments)]
+pub fn create_archive(
+    root: &Path,
+    dir_path: &str,
+    paths: &[String],
+    format: ArchiveFormat,
+    target: &str,
+    options: &ManageOptions,
+    on_progress: &mut ProgressHandler,
+) -> Result<(), AnyError> {
+    let full_path = resolve_path(root, dir_path, options.symlinks)?;
+    let items = get_archive_items(root, &full_path, paths, options.symlinks)?;
+    let target = match target.is_empty() {
+        true => get_archive_name(root, &full_path, format),
+        false => target.to_string(),
+    };
+    let to = resolve_new_path(root, dir_path, &target, options.symlinks)?;
+    let to = match resolve_conflict(&to, options.on_conflict)? {
+        Some(to) => to,
+        None => return Ok(()),
+    };
+    // archive is saved as temp file beside target, existing target is moved into trash only when done
+    let temp = get_temp_path(&to, ARCHIVE_FILE_SUFFIX)?;
+    trace!("create_archive(), to={:?}, temp={:?}", &to, &temp);
+    let result = save_archive(format, &items, &temp, on_progress)
+        .and_then(|_| Trash::new(root).replace(&temp, &to));
+    if result.is_err() {
+        let _ = remove_existing(&temp);
+    }
+    result
+}
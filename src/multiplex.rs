@@ -107,7 +107,7 @@ where
     }
 }
 
-fn is_grpc_request<B>(req: &Request<B>) -> bool {
+pub fn is_grpc_request<B>(req: &Request<B>) -> bool {
     req.headers()
         .get(CONTENT_TYPE)
         .map(|content_type| content_type.as_bytes())
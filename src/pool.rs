@@ -0,0 +1,47 @@
+use log::trace;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+type AnyError = anyhow::Error;
+
+/// Default workers of quick filesystem calls like listing and uploading, management has its own
+pub const IO_WORKERS: usize = 64;
+/// Default workers of long running streams like archive downloads and searches,
+/// they have their own workers so they never take all workers of quick calls
+pub const STREAM_WORKERS: usize = 8;
+
+/// Bounded pool running blocking filesystem work off the async runtime.
+/// Work waits for a free worker, so slow disks never starve other requests.
+#[derive(Debug, Clone)]
+pub struct FsPool {
+    name: &'static str,
+    semaphore: Arc<Semaphore>,
+}
+
+impl FsPool {
+    pub fn new(name: &'static str, size: usize) -> Self {
+        FsPool {
+            name,
+            semaphore: Arc::new(Semaphore::new(size.max(1))),
+        }
+    }
+
+    /// Run blocking work in the pool, the worker is released when it's done
+    pub async fn run<F, T>(&self, f: F) -> Result<T, AnyError>
+    where
+        F: FnOnce() -> Result<T, AnyError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self.semaphore.clone().acquire_owned().await?;
+        trace!(
+            "FsPool::run(), name={}, available={}",
+            self.name,
+            self.semaphore.available_permits()
+        );
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f()
+        })
+        .await?
+    }
+}
@@ -0,0 +1,98 @@
+use log::trace;
+use std::{ops::RangeInclusive, time::SystemTime};
+
+// more ranges are treated as abuse, and the header is ignored
+const MAX_RANGE_COUNT: usize = 64;
+
+fn parse_range(spec: &str, size: u64) -> Result<Option<RangeInclusive<u64>>, ()> {
+    let (start, end) = spec.trim().split_once('-').ok_or(())?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = match (start.is_empty(), end.is_empty()) {
+        // suffix range, e.g. -500 for the last 500 bytes
+        (true, false) => {
+            let suffix = end.parse::<u64>().map_err(|_| ())?;
+            match suffix {
+                0 => None,
+                _ => Some(size.saturating_sub(suffix)..=size.saturating_sub(1)),
+            }
+        }
+        (false, _) => {
+            let start = start.parse::<u64>().map_err(|_| ())?;
+            let end = match end.is_empty() {
+                true => u64::MAX,
+                false => end.parse::<u64>().map_err(|_| ())?,
+            };
+            if end < start {
+                return Err(());
+            }
+            // end after EOF is clamped, while start after EOF is unsatisfiable
+            Some(start..=end.min(size.saturating_sub(1)))
+        }
+        (true, true) => return Err(()),
+    };
+    Ok(range.filter(|range| size > 0 && range.start() < &size))
+}
+
+/// Parse value of Range header with file size, overlapping and adjacent ranges are merged.
+/// None means the header should be ignored, and empty ranges means it's not satisfiable.
+pub fn parse_ranges(value: &str, size: u64) -> Option<Vec<RangeInclusive<u64>>> {
+    let specs = value.trim().strip_prefix("bytes=")?;
+    let specs: Vec<&str> = specs.split(',').filter(|s| !s.trim().is_empty()).collect();
+    if specs.is_empty() || specs.len() > MAX_RANGE_COUNT {
+        return None;
+    }
+    let mut ranges = vec![];
+    for spec in specs {
+        if let Some(range) = parse_range(spec, size).ok()? {
+            ranges.push(range);
+        }
+    }
+    let ranges = merge_ranges(ranges);
+    trace!("parse_ranges(), value={}, ranges={:?}", value, ranges);
+    Some(ranges)
+}
+
+pub fn merge_ranges(mut ranges: Vec<RangeInclusive<u64>>) -> Vec<RangeInclusive<u64>> {
+    ranges.sort_by_key(|range| *range.start());
+    let mut merged: Vec<RangeInclusive<u64>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end().saturating_add(1) => {
+                *last = *last.start()..=*last.end().max(range.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+pub fn generate_boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("serva_boundary_{:032x}", nanos)
+}
+
+/// Header of a part in multipart/byteranges body, the first one has no leading CRLF
+pub fn get_part_header(
+    boundary: &str,
+    mime: &str,
+    range: &RangeInclusive<u64>,
+    size: u64,
+    is_first: bool,
+) -> String {
+    format!(
+        "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+        if is_first { "" } else { "\r\n" },
+        boundary,
+        mime,
+        range.start(),
+        range.end(),
+        size
+    )
+}
+
+pub fn get_closing_boundary(boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
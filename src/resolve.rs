@@ -0,0 +1,544 @@
+use crate::trash::TRASH_DIR_NAME;
+use log::trace;
+use std::{
+    fmt::{Display, Formatter},
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// How symlinks under root are resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Symlinks are never followed
+    Deny,
+    /// Symlinks are followed when their targets are inside root
+    WithinRoot,
+    /// Symlinks are always followed, even to targets outside root
+    Follow,
+}
+
+/// Errors of resolving paths from clients under root
+#[derive(Debug)]
+pub enum PathError {
+    /// Absolute path or path with prefix like C:
+    Absolute(String),
+    /// Path with .. component
+    ParentDir(String),
+    /// Name of new entry is empty, . or .., or contains separators
+    InvalidName(String),
+    NotFound(PathBuf),
+    NotDir(PathBuf),
+    /// Symlink found under --symlinks=deny
+    SymlinkDenied(PathBuf),
+    /// Symlink to outside of root under --symlinks=within-root
+    OutsideRoot(PathBuf),
+    /// Trash dir or entry in it, which is managed only by trash api
+    InTrash(PathBuf),
+    Io(PathBuf, io::Error),
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            PathError::Absolute(path) => write!(f, "absolute path is forbidden: {}", path),
+            PathError::ParentDir(path) => write!(f, "accessing parent dir is forbidden: {}", path),
+            PathError::InvalidName(name) => write!(f, "invalid name: {}", name),
+            PathError::NotFound(path) => write!(f, "not found: {:?}", path),
+            PathError::NotDir(path) => write!(f, "not a dir: {:?}", path),
+            PathError::SymlinkDenied(path) => write!(f, "symlink is denied: {:?}", path),
+            PathError::OutsideRoot(path) => write!(f, "symlink points outside root: {:?}", path),
+            PathError::InTrash(path) => write!(f, "trash is managed by trash api only: {:?}", path),
+            PathError::Io(path, e) => write!(f, "failed to resolve {:?}: {}", path, e),
+        }
+    }
+}
+
+impl std::error::Error for PathError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PathError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+fn get_io_error(path: &Path, e: io::Error) -> PathError {
+    match e.kind() {
+        io::ErrorKind::NotFound => PathError::NotFound(path.to_path_buf()),
+        _ => PathError::Io(path.to_path_buf(), e),
+    }
+}
+
+/// Split relative path into names, both / and \ are separators, so paths from windows clients
+/// are handled the same way on all platforms. Empty and . components are skipped.
+pub fn get_names(path: &str) -> Result<Vec<&str>, PathError> {
+    let is_prefixed = path.starts_with(['/', '\\'])
+        || matches!(path.as_bytes(), [drive, b':', ..] if drive.is_ascii_alphabetic());
+    if is_prefixed {
+        return Err(PathError::Absolute(path.to_string()));
+    }
+    let mut names = vec![];
+    for name in path.split(['/', '\\']) {
+        match name {
+            "" | "." => {}
+            ".." => return Err(PathError::ParentDir(path.to_string())),
+            _ => names.push(name),
+        }
+    }
+    Ok(names)
+}
+
+/// Validate name of a new entry, which must be a single component
+pub fn validate_name(name: &str) -> Result<(), PathError> {
+    if matches!(name, "" | "." | "..") || name.contains(['/', '\\', '\0']) {
+        return Err(PathError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Check that path under root is not the trash dir or inside it, every path from clients is
+/// checked, so the trash is reached only by trash api
+pub fn check_not_in_trash(root: &Path, path: &Path) -> Result<(), PathError> {
+    match path.starts_with(root.join(TRASH_DIR_NAME)) {
+        true => Err(PathError::InTrash(path.to_path_buf())),
+        false => Ok(()),
+    }
+}
+
+// follow symlink at path by policy, returns the resolved target
+fn follow_symlink(root: &Path, path: &Path, symlinks: SymlinkPolicy) -> Result<PathBuf, PathError> {
+    if symlinks == SymlinkPolicy::Deny {
+        return Err(PathError::SymlinkDenied(path.to_path_buf()));
+    }
+    let target = path.canonicalize().map_err(|e| get_io_error(path, e))?;
+    if symlinks == SymlinkPolicy::WithinRoot && !target.starts_with(root) {
+        return Err(PathError::OutsideRoot(path.to_path_buf()));
+    }
+    trace!("follow_symlink(), path={:?}, target={:?}", path, &target);
+    Ok(target)
+}
+
+/// Get the target of symlink at path if it's allowed to follow, used for listing dirs
+pub fn get_symlink_target(root: &Path, path: &Path, symlinks: SymlinkPolicy) -> Option<PathBuf> {
+    follow_symlink(root, path, symlinks).ok()
+}
+
+// check that the real path of an existing entry is allowed by policy, it must be the same
+// path under deny, since there is no symlink in it, and under root under within-root
+fn check_real_path(
+    root: &Path,
+    path: &Path,
+    real_path: &Path,
+    symlinks: SymlinkPolicy,
+) -> Result<(), PathError> {
+    match symlinks {
+        SymlinkPolicy::Deny if real_path != path => {
+            Err(PathError::SymlinkDenied(path.to_path_buf()))
+        }
+        SymlinkPolicy::WithinRoot if !real_path.starts_with(root) => {
+            Err(PathError::OutsideRoot(path.to_path_buf()))
+        }
+        SymlinkPolicy::Follow => Ok(()),
+        _ => check_not_in_trash(root, real_path),
+    }
+}
+
+// components could be swapped with symlinks after being checked by lstat, so the existing
+// part of resolved path is canonicalized and checked again right before it's returned.
+// It narrows the window but doesn't close it, the path could still be swapped before it's
+// used, so files served to clients are opened by open_resolved_file, which checks them again.
+fn recheck_resolved(
+    root: &Path,
+    resolved: &Path,
+    symlinks: SymlinkPolicy,
+    follow_last: bool,
+) -> Result<(), PathError> {
+    // last component which is not followed may be a symlink itself
+    let start = match follow_last {
+        true => resolved,
+        false => resolved.parent().unwrap_or(resolved),
+    };
+    let existing = match start
+        .ancestors()
+        .take_while(|path| path.starts_with(root))
+        .find(|path| path.symlink_metadata().is_ok())
+    {
+        Some(existing) => existing,
+        None => return Ok(()),
+    };
+    let real_path = existing
+        .canonicalize()
+        .map_err(|e| get_io_error(existing, e))?;
+    check_real_path(root, existing, &real_path, symlinks)
+}
+
+// walk names from base, every existing component is checked with lstat, so symlinks are
+// handled by policy before being followed. Missing components are allowed only when
+// must_exist is false, they contain no symlink since they don't exist.
+fn resolve_names(
+    root: &Path,
+    base: &Path,
+    names: &[&str],
+    symlinks: SymlinkPolicy,
+    follow_last: bool,
+    must_exist: bool,
+) -> Result<PathBuf, PathError> {
+    let mut resolved = base.to_path_buf();
+    for (i, name) in names.iter().enumerate() {
+        let path = resolved.join(name);
+        let is_last = i + 1 == names.len();
+        match path.symlink_metadata() {
+            Ok(metadata) if metadata.is_symlink() && (follow_last || !is_last) => {
+                // under follow policy, the path is kept so it could be stripped by root
+                resolved = match symlinks {
+                    SymlinkPolicy::Follow => {
+                        follow_symlink(root, &path, symlinks)?;
+                        path
+                    }
+                    _ => follow_symlink(root, &path, symlinks)?,
+                };
+            }
+            Ok(_) => resolved = path,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !must_exist => {
+                let resolved = names[i..]
+                    .iter()
+                    .fold(resolved, |path, name| path.join(name));
+                check_not_in_trash(root, &resolved)?;
+                recheck_resolved(root, &resolved, symlinks, follow_last)?;
+                return Ok(resolved);
+            }
+            Err(e) => return Err(get_io_error(&path, e)),
+        }
+        // checked at every step, so symlinks never lead into trash either
+        check_not_in_trash(root, &resolved)?;
+    }
+    recheck_resolved(root, &resolved, symlinks, follow_last)?;
+    Ok(resolved)
+}
+
+// get the real path of opened file, from the file itself on linux, so it's the file being read
+// even when its path is swapped after opening
+#[cfg(target_os = "linux")]
+fn get_opened_path(file: &File, _path: &Path) -> io::Result<PathBuf> {
+    use std::os::unix::io::AsRawFd;
+    std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))
+}
+
+// get the canonical path of opened file, which must be the same file as opened, it leaves
+// a small window when the canonical path is swapped too
+#[cfg(not(target_os = "linux"))]
+fn get_opened_path(file: &File, path: &Path) -> io::Result<PathBuf> {
+    let real_path = path.canonicalize()?;
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let (opened, real) = (file.metadata()?, real_path.metadata()?);
+        if opened.dev() != real.dev() || opened.ino() != real.ino() {
+            return Err(io::Error::other("file is swapped"));
+        }
+    }
+    #[cfg(target_os = "windows")]
+    let _ = file;
+    Ok(real_path)
+}
+
+/// Open resolved file for reading, the opened file itself is checked by policy again,
+/// so it's never a file outside root even when components are swapped after resolving
+pub fn open_resolved_file(
+    root: &Path,
+    path: &Path,
+    symlinks: SymlinkPolicy,
+) -> Result<File, PathError> {
+    let file = File::open(path).map_err(|e| get_io_error(path, e))?;
+    if symlinks != SymlinkPolicy::Follow {
+        let opened_path = get_opened_path(&file, path).map_err(|e| get_io_error(path, e))?;
+        trace!("open_resolved_file(), opened_path={:?}", &opened_path);
+        check_real_path(root, path, &opened_path, symlinks)?;
+    }
+    Ok(file)
+}
+
+/// Resolve existing path relative to root, symlinks are followed by policy
+pub fn resolve_path<P>(root: &Path, path: P, symlinks: SymlinkPolicy) -> Result<PathBuf, PathError>
+where
+    P: AsRef<Path>,
+{
+    resolve_path_from(root, root, path, symlinks)
+}
+
+/// Resolve existing path relative to base, which is a resolved path under root
+pub fn resolve_path_from<P>(
+    root: &Path,
+    base: &Path,
+    path: P,
+    symlinks: SymlinkPolicy,
+) -> Result<PathBuf, PathError>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref().to_string_lossy();
+    let names = get_names(&path)?;
+    resolve_names(root, base, &names, symlinks, true, true)
+}
+
+/// Resolve existing entry relative to root without following itself if it's a symlink,
+/// used for operations on the entry itself, like deleting, moving or renaming
+pub fn resolve_entry_path(
+    root: &Path,
+    path: &str,
+    symlinks: SymlinkPolicy,
+) -> Result<PathBuf, PathError> {
+    let names = get_names(path)?;
+    resolve_names(root, root, &names, symlinks, false, true)
+}
+
+/// Resolve path of entry named name in existing dir, the entry may not exist yet.
+/// Existing symlink of the entry is checked by policy since writing to it follows it.
+pub fn resolve_new_path(
+    root: &Path,
+    dir_path: &str,
+    name: &str,
+    symlinks: SymlinkPolicy,
+) -> Result<PathBuf, PathError> {
+    validate_name(name)?;
+    let dir = resolve_path(root, dir_path, symlinks)?;
+    if !dir.is_dir() {
+        return Err(PathError::NotDir(dir));
+    }
+    resolve_names(root, &dir, &[name], symlinks, true, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // names of single components, never . or .. and without separators
+    fn name() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9_ .-]{1,12}".prop_filter("not . or ..", |name| name != "." && name != "..")
+    }
+
+    fn names() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec(name(), 1..5)
+    }
+
+    fn separator() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec!["/", "\\", "//", "/./", "\\.\\"])
+    }
+
+    // join names with random separators of both unix and windows
+    fn join_names(names: &[String], separators: &[&str]) -> String {
+        let mut path = names[0].clone();
+        for (name, separator) in names[1..].iter().zip(separators.iter().cycle()) {
+            path.push_str(separator);
+            path.push_str(name);
+        }
+        path
+    }
+
+    // canonical root like the server uses, with an outside dir next to it
+    fn create_root() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let temp = tempfile::tempdir().unwrap();
+        let base = temp.path().canonicalize().unwrap();
+        let root = base.join("root");
+        let outside = base.join("outside");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        (temp, root, outside)
+    }
+
+    proptest! {
+        #[test]
+        fn get_names_splits_both_separators(
+            names in names(),
+            separators in prop::collection::vec(separator(), 1..5),
+        ) {
+            let path = join_names(&names, &separators);
+            prop_assert_eq!(get_names(&path).unwrap(), names);
+        }
+
+        #[test]
+        fn get_names_rejects_parent_dir(
+            before in prop::collection::vec(name(), 0..3),
+            after in prop::collection::vec(name(), 0..3),
+            separator in separator(),
+        ) {
+            let names: Vec<String> = before
+                .into_iter()
+                .chain(std::iter::once("..".to_string()))
+                .chain(after)
+                .collect();
+            let path = names.join(separator);
+            prop_assert!(matches!(get_names(&path), Err(PathError::ParentDir(_))));
+        }
+
+        #[test]
+        fn get_names_rejects_absolute(
+            path in "[a-z0-9/\\\\]{0,12}",
+            prefix in prop::sample::select(vec!["/", "\\", "C:", "c:\\", "Z:/"]),
+        ) {
+            let path = format!("{}{}", prefix, path);
+            prop_assert!(matches!(get_names(&path), Err(PathError::Absolute(_))));
+        }
+
+        #[test]
+        fn validate_name_accepts_single_component(name in name()) {
+            prop_assert!(validate_name(&name).is_ok());
+        }
+
+        #[test]
+        fn validate_name_rejects_separators(
+            before in "[a-z.]{0,4}",
+            after in "[a-z.]{0,4}",
+            separator in prop::sample::select(vec!["/", "\\", "\0"]),
+        ) {
+            let name = format!("{}{}{}", before, separator, after);
+            prop_assert!(matches!(validate_name(&name), Err(PathError::InvalidName(_))));
+        }
+
+        #[test]
+        fn resolve_path_stays_under_root(
+            names in names(),
+            separators in prop::collection::vec(separator(), 1..5),
+        ) {
+            let (_temp, root, _) = create_root();
+            let expected = names.iter().fold(root.clone(), |path, name| path.join(name));
+            std::fs::create_dir_all(&expected).unwrap();
+            let path = join_names(&names, &separators);
+            for symlinks in [SymlinkPolicy::Deny, SymlinkPolicy::WithinRoot, SymlinkPolicy::Follow] {
+                prop_assert_eq!(resolve_path(&root, &path, symlinks).unwrap(), expected.clone());
+                let escaping = format!("{}/../../outside", path);
+                prop_assert!(resolve_path(&root, &escaping, symlinks).is_err());
+                let absolute = expected.to_string_lossy().to_string();
+                prop_assert!(resolve_path(&root, &absolute, symlinks).is_err());
+            }
+        }
+
+        #[test]
+        fn resolve_new_path_joins_name_in_dir(dir_names in names(), name in name()) {
+            let (_temp, root, _) = create_root();
+            let dir = dir_names.iter().fold(root.clone(), |path, name| path.join(name));
+            std::fs::create_dir_all(&dir).unwrap();
+            let dir_path = dir_names.join("\\");
+            for symlinks in [SymlinkPolicy::Deny, SymlinkPolicy::WithinRoot, SymlinkPolicy::Follow] {
+                let path = resolve_new_path(&root, &dir_path, &name, symlinks).unwrap();
+                prop_assert_eq!(path, dir.join(&name));
+                for invalid in ["", ".", "..", "a/b", "a\\b"] {
+                    prop_assert!(resolve_new_path(&root, &dir_path, invalid, symlinks).is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn trash_is_never_resolved() {
+        let (_temp, root, _) = create_root();
+        std::fs::create_dir_all(root.join(TRASH_DIR_NAME).join("files")).unwrap();
+        for symlinks in [
+            SymlinkPolicy::Deny,
+            SymlinkPolicy::WithinRoot,
+            SymlinkPolicy::Follow,
+        ] {
+            for path in [
+                TRASH_DIR_NAME,
+                ".serva-trash/files",
+                "./.serva-trash\\files\\x",
+            ] {
+                let resolved = resolve_path(&root, path, symlinks);
+                assert!(matches!(resolved, Err(PathError::InTrash(_))), "{}", path);
+                let resolved = resolve_entry_path(&root, path, symlinks);
+                assert!(matches!(resolved, Err(PathError::InTrash(_))), "{}", path);
+            }
+            let resolved = resolve_new_path(&root, "", TRASH_DIR_NAME, symlinks);
+            assert!(matches!(resolved, Err(PathError::InTrash(_))));
+            let resolved = resolve_new_path(&root, TRASH_DIR_NAME, "x", symlinks);
+            assert!(matches!(resolved, Err(PathError::InTrash(_))));
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    proptest! {
+        #[test]
+        fn symlinks_are_resolved_by_policy(names in names()) {
+            use std::os::unix::fs::symlink;
+            let (_temp, root, outside) = create_root();
+            let inside = root.join("inside");
+            let sub = names.iter().fold(PathBuf::new(), |path, name| path.join(name));
+            std::fs::create_dir_all(inside.join(&sub)).unwrap();
+            std::fs::create_dir_all(outside.join(&sub)).unwrap();
+            symlink(&inside, root.join("in_link")).unwrap();
+            symlink(&outside, root.join("out_link")).unwrap();
+            let sub_path = names.join("/");
+            let in_path = format!("in_link/{}", sub_path);
+            let out_path = format!("out_link/{}", sub_path);
+
+            let deny = SymlinkPolicy::Deny;
+            prop_assert!(matches!(resolve_path(&root, &in_path, deny), Err(PathError::SymlinkDenied(_))));
+            prop_assert!(matches!(resolve_path(&root, &out_path, deny), Err(PathError::SymlinkDenied(_))));
+            prop_assert!(matches!(resolve_new_path(&root, "", "in_link", deny), Err(PathError::SymlinkDenied(_))));
+            prop_assert_eq!(resolve_entry_path(&root, "in_link", deny).unwrap(), root.join("in_link"));
+
+            let within = SymlinkPolicy::WithinRoot;
+            prop_assert_eq!(resolve_path(&root, &in_path, within).unwrap(), inside.join(&sub));
+            prop_assert!(matches!(resolve_path(&root, &out_path, within), Err(PathError::OutsideRoot(_))));
+            prop_assert!(matches!(resolve_new_path(&root, "", "out_link", within), Err(PathError::OutsideRoot(_))));
+            prop_assert_eq!(resolve_entry_path(&root, "out_link", within).unwrap(), root.join("out_link"));
+
+            // followed paths are kept under root, so they could be stripped by root
+            let follow = SymlinkPolicy::Follow;
+            prop_assert_eq!(resolve_path(&root, &in_path, follow).unwrap(), root.join("in_link").join(&sub));
+            prop_assert_eq!(resolve_path(&root, &out_path, follow).unwrap(), root.join("out_link").join(&sub));
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        // a dir is swapped with a symlink to outside while resolving and opening, files opened
+        // from resolved paths must never be the outside file unless symlinks are followed on purpose
+        #[test]
+        fn symlink_race_never_reads_outside(names in names(), rounds in 50..200usize) {
+            use std::{io::Read, os::unix::fs::symlink, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
+            let (_temp, root, outside) = create_root();
+            let sub = names.iter().fold(PathBuf::new(), |path, name| path.join(name));
+            let racing = root.join("racing");
+            let real = root.join("real");
+            std::fs::create_dir_all(real.join(&sub)).unwrap();
+            std::fs::create_dir_all(outside.join(&sub)).unwrap();
+            std::fs::write(real.join(&sub).join("file"), "inside").unwrap();
+            std::fs::write(outside.join(&sub).join("file"), "outside").unwrap();
+            std::fs::rename(&real, &racing).unwrap();
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let swapper = {
+                let (stop, racing, real, outside) = (stop.clone(), racing.clone(), real.clone(), outside.clone());
+                std::thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        let _ = std::fs::rename(&racing, &real);
+                        let _ = symlink(&outside, &racing);
+                        std::thread::sleep(Duration::from_micros(50));
+                        let _ = std::fs::remove_file(&racing);
+                        let _ = std::fs::rename(&real, &racing);
+                        std::thread::sleep(Duration::from_micros(50));
+                    }
+                })
+            };
+            let path = format!("racing/{}/file", names.join("/"));
+            for _ in 0..rounds {
+                for symlinks in [SymlinkPolicy::Deny, SymlinkPolicy::WithinRoot] {
+                    let file = resolve_path(&root, &path, symlinks)
+                        .and_then(|resolved| open_resolved_file(&root, &resolved, symlinks));
+                    if let Ok(mut file) = file {
+                        let mut data = String::new();
+                        file.read_to_string(&mut data).unwrap();
+                        prop_assert_eq!(data.as_str(), "inside");
+                    }
+                }
+            }
+            stop.store(true, Ordering::Relaxed);
+            swapper.join().unwrap();
+        }
+    }
+}
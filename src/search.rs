@@ -0,0 +1,148 @@
+use crate::trash::TRASH_DIR_NAME;
+use log::trace;
+use regex::{Regex, RegexBuilder};
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+type AnyError = anyhow::Error;
+
+/// Convert glob of names to anchored regex, supports *, ? and [...] (with ! or ^ for negation)
+pub fn glob_to_regex(glob: &str, case_sensitive: bool) -> Result<Regex, AnyError> {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '[' => {
+                // copy the class until ']', escaping chars special in regex class
+                let mut class = String::from("[");
+                let mut raw = String::from("[");
+                if let Some(negation) = chars.next_if(|c| *c == '!' || *c == '^') {
+                    class.push('^');
+                    raw.push(negation);
+                }
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    raw.push(c);
+                    match c {
+                        ']' => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' | '[' | '&' | '~' => {
+                            class.push('\\');
+                            class.push(c);
+                        }
+                        _ => class.push(c),
+                    }
+                }
+                // unclosed class is matched literally
+                match closed {
+                    true => pattern.push_str(&format!("{}]", class)),
+                    false => pattern.push_str(&regex::escape(&raw)),
+                }
+            }
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    trace!("glob_to_regex(), glob={}, pattern={}", glob, pattern);
+    Ok(RegexBuilder::new(&pattern)
+        .case_insensitive(!case_sensitive)
+        .build()?)
+}
+
+/// Filter of entries, None means no limit
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+    pub name: Option<Regex>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub min_modified: Option<SystemTime>,
+    pub max_modified: Option<SystemTime>,
+    pub include_dirs: bool,
+}
+
+impl SearchFilter {
+    pub fn is_match(&self, name: &str, metadata: &Metadata) -> bool {
+        if metadata.is_dir() && !self.include_dirs {
+            return false;
+        }
+        if matches!(&self.name, Some(regex) if !regex.is_match(name)) {
+            return false;
+        }
+        // size ranges only apply to files
+        if metadata.is_file() {
+            let size = metadata.len();
+            if matches!(self.min_size, Some(min) if size < min)
+                || matches!(self.max_size, Some(max) if size > max)
+            {
+                return false;
+            }
+        }
+        if self.min_modified.is_some() || self.max_modified.is_some() {
+            let modified = match metadata.modified() {
+                Ok(modified) => modified,
+                Err(_) => return false,
+            };
+            if matches!(self.min_modified, Some(min) if modified < min)
+                || matches!(self.max_modified, Some(max) if modified > max)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Walk the tree under dir and call on_match for each matched dir or file, symlinks are skipped.
+/// Walking stops when on_match returns false, or is_cancelled returns true, which is checked
+/// on every entry, so a search matching nothing stops as soon as it's cancelled too.
+pub fn search_files<F, C>(
+    dir: &Path,
+    filter: &SearchFilter,
+    mut on_match: F,
+    is_cancelled: C,
+) -> Result<(), AnyError>
+where
+    F: FnMut(&Path, &Metadata) -> bool,
+    C: Fn() -> bool,
+{
+    let mut dirs: Vec<PathBuf> = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                trace!("search_files(), skip {:?}, error={:?}", &dir, e);
+                continue;
+            }
+        };
+        // entries failed to read are skipped, like unreadable sub dirs
+        for entry in entries.flatten() {
+            if is_cancelled() {
+                trace!("search_files(), cancelled");
+                return Ok(());
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() || metadata.is_file() => metadata,
+                _ => continue,
+            };
+            let path = entry.path();
+            let name = entry.file_name();
+            if name == TRASH_DIR_NAME {
+                continue;
+            }
+            if filter.is_match(&name.to_string_lossy(), &metadata) && !on_match(&path, &metadata) {
+                return Ok(());
+            }
+            if metadata.is_dir() {
+                dirs.push(path);
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,13 @@
+use anyhow::anyhow;
 use axum::{
-    body::{Full, StreamBody},
-    extract::Path as AxumPath,
+    body::{Bytes, Full, StreamBody},
+    extract::{BodyStream, Multipart, Path as AxumPath, RawQuery},
     http::{header, StatusCode},
     response::{AppendHeaders, IntoResponse, Redirect, Response},
     routing::get,
     Extension, Router,
 };
+use futures::StreamExt;
 use http::{
     header::{
         ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE,
@@ -14,47 +16,81 @@ use http::{
 };
 use log::{debug, trace};
 use std::{
-    error::Error,
-    io::SeekFrom,
+    fs::File,
+    io::{self, SeekFrom},
     ops::RangeInclusive,
     path::{Path, PathBuf},
     sync::Arc,
 };
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, Take};
 use tokio_util::io::ReaderStream;
 use tower_http::cors::CorsLayer;
 
-use crate::data::{get_valid_joined_path, Asset, ServerInfo};
+use crate::{
+    archive::{get_archive_items, get_archive_name, get_archive_stream, ArchiveFormat},
+    conditional::{evaluate_preconditions, is_range_allowed, Precondition, Validator},
+    conflict::ConflictPolicy,
+    data::{Asset, ServaError, ServerInfo},
+    pool::FsPool,
+    range::{generate_boundary, get_closing_boundary, get_part_header, parse_ranges},
+    resolve::{open_resolved_file, resolve_path, SymlinkPolicy},
+    thumbnail::{get_cache_dir, get_thumbnail, parse_thumbnail_size, THUMBNAIL_WORKERS},
+    upload::{get_upload_path_names, UploadFileWriter, UploadTarget},
+    user::UserProfile,
+};
 
-// type AnyError = anyhow::Error;
+type AnyError = anyhow::Error;
 type ExtConfig = Extension<Arc<Config>>;
+type ExtUser = Extension<Arc<UserProfile>>;
 
 #[derive(Debug)]
 pub struct Config {
-    root: PathBuf,
     prefix: String,
     allow_cors: bool,
-    allow_download: bool,
+    inline_types: Vec<String>,
+    thumbnail_dir: Option<PathBuf>,
+    symlinks: SymlinkPolicy,
+    io_pool: FsPool,
+    stream_pool: FsPool,
+    thumbnail_pool: FsPool,
 }
 
 impl From<&ServerInfo> for Config {
     fn from(server_info: &ServerInfo) -> Self {
         Config {
-            root: server_info.root.clone(),
             prefix: server_info.prefix.clone(),
             allow_cors: server_info.arg_allow_cors,
-            allow_download: server_info.arg_allow_download,
+            inline_types: server_info.arg_inline_types.clone(),
+            thumbnail_dir: get_cache_dir().ok(),
+            symlinks: server_info.arg_symlinks,
+            io_pool: server_info.io_pool.clone(),
+            stream_pool: server_info.stream_pool.clone(),
+            thumbnail_pool: FsPool::new("thumbnail", THUMBNAIL_WORKERS),
         }
     }
 }
 
+impl Config {
+    // types are like image/png, image/* or */*
+    fn is_inline_type(&self, mime: &mime_guess::Mime) -> bool {
+        self.inline_types
+            .iter()
+            .any(|inline_type| match inline_type.split_once('/') {
+                Some((type_, "*")) => type_ == "*" || type_ == mime.type_().as_str(),
+                _ => inline_type == mime.essence_str(),
+            })
+    }
+}
+
 macro_rules! unwrap_result_or_return {
     ($result: expr) => {
         match $result {
             Ok(r) => r,
             Err(e) => {
                 trace!("Error: {:?}", e);
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                return ServaError::from(AnyError::from(e))
+                    .get_status_code()
+                    .into_response();
             }
         }
     };
@@ -79,12 +115,94 @@ macro_rules! unwrap_option_or_return {
             }
         }
     };
+    ($option: expr, $resp: expr) => {
+        match $option {
+            Some(r) => r,
+            None => {
+                trace!("Failed to unwrap Option");
+                return $resp.into_response();
+            }
+        }
+    };
+}
+
+// query of fs files, e.g. ?archive=zip&path=a&path=b
+#[derive(Debug, Default)]
+struct FsQuery {
+    archive: Option<ArchiveFormat>,
+    paths: Vec<String>,
+    inline: Option<bool>,
+    thumb: Option<(u32, u32)>,
+    on_conflict: Option<ConflictPolicy>,
 }
 
-fn build_response_from_result(result: Result<impl IntoResponse, impl Error>) -> Response {
+fn parse_bool(value: &str) -> Result<bool, AnyError> {
+    match value {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(anyhow!("invalid bool value {:?}", value)),
+    }
+}
+
+impl FsQuery {
+    fn parse(query: Option<&str>) -> Result<Self, AnyError> {
+        let mut fs_query = FsQuery::default();
+        let pairs: Vec<(String, String)> = match query {
+            Some(query) => serde_urlencoded::from_str(query)?,
+            None => vec![],
+        };
+        for (name, value) in pairs {
+            match name.as_str() {
+                // bare ?archive means zip
+                "archive" if value.is_empty() => fs_query.archive = Some(ArchiveFormat::Zip),
+                "archive" => fs_query.archive = Some(value.parse()?),
+                "path" => fs_query.paths.push(value),
+                "inline" => fs_query.inline = Some(parse_bool(&value)?),
+                "download" => fs_query.inline = Some(!parse_bool(&value)?),
+                "thumb" => fs_query.thumb = Some(parse_thumbnail_size(&value)?),
+                "on_conflict" => fs_query.on_conflict = Some(value.parse()?),
+                _ => {}
+            }
+        }
+        Ok(fs_query)
+    }
+}
+
+// filename* in RFC 5987 keeps non-ascii names, and filename is the fallback for old clients
+fn get_content_disposition(inline: bool, file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (b as char).to_string(),
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        if inline { "inline" } else { "attachment" },
+        fallback,
+        encoded
+    )
+}
+
+fn build_response_from_result(result: Result<impl IntoResponse, impl Into<AnyError>>) -> Response {
     match result {
         Ok(response) => response.into_response(),
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(e) => {
+            let e = e.into();
+            trace!("Error: {:?}", e);
+            ServaError::from(e).get_status_code().into_response()
+        }
     }
 }
 
@@ -93,24 +211,36 @@ fn build_range_error_response(size: u64) -> Response {
     (append_headers, StatusCode::RANGE_NOT_SATISFIABLE).into_response()
 }
 
-async fn build_range_response(file_path: &Path, range: &RangeInclusive<u64>) -> Response {
-    let mut file = match tokio::fs::File::open(file_path).await {
-        Ok(file) => file,
+async fn get_range_stream(
+    file: File,
+    range: &RangeInclusive<u64>,
+) -> io::Result<ReaderStream<Take<tokio::fs::File>>> {
+    let mut file = tokio::fs::File::from_std(file);
+    file.seek(SeekFrom::Start(*range.start())).await?;
+    Ok(ReaderStream::new(
+        file.take(range.end() - range.start() + 1),
+    ))
+}
+
+async fn build_range_response(
+    file_path: &Path,
+    file: File,
+    range: &RangeInclusive<u64>,
+    length: u64,
+) -> Response {
+    // build body from range
+    let stream = match get_range_stream(file, range).await {
+        Ok(stream) => stream,
         Err(_) => return StatusCode::NOT_FOUND.into_response(),
     };
-    // build body from range
-    let start = *range.start();
-    if (file.seek(SeekFrom::Start(start)).await).is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-    }
-    let end = *range.end();
+    let (start, end) = (*range.start(), *range.end());
     let size = end - start + 1;
-    let body = StreamBody::new(ReaderStream::new(file.take(size)));
+    let body = StreamBody::new(stream);
     let mime = mime_guess::from_path(file_path).first_or_octet_stream();
     let headers = AppendHeaders([
         (CONTENT_TYPE, mime.to_string()),
         (ACCEPT_RANGES, "bytes".to_string()),
-        (CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size)),
+        (CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, length)),
         (CONTENT_LENGTH, size.to_string()),
     ]);
     trace!(
@@ -122,23 +252,110 @@ async fn build_range_response(file_path: &Path, range: &RangeInclusive<u64>) ->
     (StatusCode::PARTIAL_CONTENT, headers, body).into_response()
 }
 
-async fn serve_embedded_files(path: &Path) -> Response {
+async fn build_multi_range_response(
+    file_path: &Path,
+    file: File,
+    ranges: Vec<RangeInclusive<u64>>,
+    length: u64,
+) -> Response {
+    let mime = mime_guess::from_path(file_path)
+        .first_or_octet_stream()
+        .to_string();
+    let boundary = generate_boundary();
+    // each part is a header followed by data of the range, and all parts are followed by closing
+    let parts: Vec<(String, RangeInclusive<u64>)> = ranges
+        .into_iter()
+        .enumerate()
+        .map(|(i, range)| {
+            (
+                get_part_header(&boundary, &mime, &range, length, i == 0),
+                range,
+            )
+        })
+        .collect();
+    let closing = get_closing_boundary(&boundary);
+    let size = parts
+        .iter()
+        .map(|(header, range)| header.len() as u64 + range.end() - range.start() + 1)
+        .sum::<u64>()
+        + closing.len() as u64;
+    trace!(
+        "build_multi_range_response(), parts={}, size={}",
+        parts.len(),
+        size
+    );
+    // parts are read one by one, so they could share the offset of the same opened file
+    let stream = futures::stream::iter(parts)
+        .then(move |(header, range)| {
+            let file = file.try_clone();
+            async move {
+                let header = futures::stream::once(async { Ok(Bytes::from(header)) });
+                let data = match file {
+                    Ok(file) => get_range_stream(file, &range).await,
+                    Err(e) => Err(e),
+                };
+                match data {
+                    Ok(data) => header.chain(data).boxed(),
+                    Err(e) => futures::stream::once(async { Err(e) }).boxed(),
+                }
+            }
+        })
+        .flatten()
+        .chain(futures::stream::once(async { Ok(Bytes::from(closing)) }));
+    let headers = AppendHeaders([
+        (
+            CONTENT_TYPE,
+            format!("multipart/byteranges; boundary={}", boundary),
+        ),
+        (ACCEPT_RANGES, "bytes".to_string()),
+        (CONTENT_LENGTH, size.to_string()),
+    ]);
+    (
+        StatusCode::PARTIAL_CONTENT,
+        headers,
+        StreamBody::new(stream),
+    )
+        .into_response()
+}
+
+fn build_embedded_response(
+    file: rust_embed::EmbeddedFile,
+    mime: &str,
+    gzip: bool,
+    headers: &HeaderMap,
+) -> Response {
+    let validator = Validator::from_embedded(&file.metadata);
+    let validator_headers = validator.get_headers();
+    match evaluate_preconditions(headers, &validator) {
+        Precondition::Passed => {}
+        Precondition::NotModified => {
+            return (StatusCode::NOT_MODIFIED, validator_headers).into_response()
+        }
+        Precondition::Failed => return StatusCode::PRECONDITION_FAILED.into_response(),
+    }
+    let mut builder = Response::builder().header(header::CONTENT_TYPE, mime);
+    if gzip {
+        builder = builder.header(header::CONTENT_ENCODING, "gzip");
+    }
+    let response = build_response_from_result(builder.body(Full::from(file.data)));
+    (validator_headers, response).into_response()
+}
+
+async fn serve_embedded_files(path: &Path, headers: &HeaderMap) -> Response {
     debug!("serve_embedded_files(), path={:?}", &path);
     let file_path = unwrap_option_or_return!(path.to_str());
 
     // when gzip exists, return gzip directly
     let mime = mime_guess::from_path(path).first_or_octet_stream();
-    let builder = Response::builder().header(header::CONTENT_TYPE, mime.as_ref());
     if let Some(gzip) = Asset::get(&format!("{}.gz", file_path)) {
         trace!("serve_embedded_files(), return gzip data directly");
-        let builder = builder.header(header::CONTENT_ENCODING, "gzip");
-        return build_response_from_result(builder.body(Full::from(gzip.data)));
+        return build_embedded_response(gzip, mime.as_ref(), true, headers);
     }
 
     // when file exists, return file data
     if let Some(file) = Asset::get(file_path) {
         trace!("serve_embedded_files(), return file data");
-        return build_response_from_result(builder.body(Full::from(file.data)));
+        return build_embedded_response(file, mime.as_ref(), false, headers);
     }
 
     // return NOT_FOUND when file not exists
@@ -146,54 +363,161 @@ async fn serve_embedded_files(path: &Path) -> Response {
     StatusCode::NOT_FOUND.into_response()
 }
 
-async fn serve_fs_files(path: &Path, headers: HeaderMap, config: ExtConfig) -> Response {
-    debug!("serve_fs_files(), path={:?}", path);
+fn serve_archive(
+    full_path: &Path,
+    format: ArchiveFormat,
+    paths: &[String],
+    user: &UserProfile,
+    config: &Config,
+) -> Response {
+    debug!("serve_archive(), format={:?}, paths={:?}", format, paths);
+    let items = unwrap_result_or_return!(get_archive_items(
+        &user.root,
+        full_path,
+        paths,
+        config.symlinks
+    ));
+    let disposition =
+        get_content_disposition(false, &get_archive_name(&user.root, full_path, format));
+    trace!("disposition={}", disposition);
+    // size is unknown before archiving, so body is sent in chunks
+    let body = StreamBody::new(get_archive_stream(
+        format,
+        items,
+        config.stream_pool.clone(),
+    ));
+    let headers = AppendHeaders([
+        (CONTENT_TYPE, format.get_mime().to_string()),
+        (CONTENT_DISPOSITION, disposition),
+    ]);
+    (headers, body).into_response()
+}
+
+async fn serve_fs_files(
+    path: &Path,
+    query: Option<&str>,
+    headers: HeaderMap,
+    config: &Config,
+    user: &UserProfile,
+) -> Response {
+    debug!("serve_fs_files(), user={}, path={:?}", user.name, path);
     trace!("serve_fs_files(), headers={:?}", headers);
 
-    // join path to get the full path of fs file
-    let full_path = unwrap_result_or_return!(
-        get_valid_joined_path(&config.root, path),
-        StatusCode::NOT_ACCEPTABLE
-    );
+    // join path to get the full path of fs file under root of user
+    let full_path = unwrap_result_or_return!(resolve_path(&user.root, path, config.symlinks));
     trace!("serve_fs_files(), full_path={:?}", full_path);
 
+    // dirs are downloaded as archives only when asked explicitly, they might be huge
+    let query = unwrap_result_or_return!(FsQuery::parse(query), StatusCode::BAD_REQUEST);
+    if let Some(format) = query.archive {
+        return serve_archive(&full_path, format, &query.paths, user, config);
+    }
+    if full_path.is_dir() {
+        trace!("serve_fs_files(), dir without archive query, return 404");
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    // thumbnail is served as a normal file from cache
+    if let Some((width, height)) = query.thumb {
+        let thumbnail_dir = unwrap_option_or_return!(config.thumbnail_dir.clone());
+        let (root, symlinks) = (user.root.clone(), config.symlinks);
+        let thumbnail_path = unwrap_result_or_return!(
+            config
+                .thumbnail_pool
+                .run(move || {
+                    let file = open_resolved_file(&root, &full_path, symlinks)?;
+                    get_thumbnail(&thumbnail_dir, &full_path, file, width, height)
+                })
+                .await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        // name thumbnail after the source file instead of the cache key
+        let file_name = format!(
+            "{}.{}",
+            path.file_stem().unwrap_or_default().to_string_lossy(),
+            thumbnail_path
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy()
+        );
+        let disposition = get_content_disposition(true, &file_name);
+        let file = unwrap_result_or_return!(tokio::fs::File::open(&thumbnail_path).await);
+        let file = file.into_std().await;
+        return serve_local_file(&thumbnail_path, file, &headers, disposition).await;
+    }
+
+    // query switch overrides the server default by mime type
+    let mime = mime_guess::from_path(&full_path).first_or_octet_stream();
+    let inline = query.inline.unwrap_or_else(|| config.is_inline_type(&mime));
+    let file_name = full_path.file_name().unwrap_or_default().to_string_lossy();
+    let disposition = get_content_disposition(inline, &file_name);
+    // opened file is checked by policy again, so it's never swapped to a file outside root
+    let (root, file_path, symlinks) = (user.root.clone(), full_path.clone(), config.symlinks);
+    let file = unwrap_result_or_return!(
+        config
+            .io_pool
+            .run(move || Ok(open_resolved_file(&root, &file_path, symlinks)?))
+            .await
+    );
+    serve_local_file(&full_path, file, &headers, disposition).await
+}
+
+async fn serve_local_file(
+    full_path: &Path,
+    file: File,
+    headers: &HeaderMap,
+    disposition: String,
+) -> Response {
+    // evaluate conditional headers with validators of file
+    let metadata = unwrap_result_or_return!(file.metadata());
+    let validator = Validator::from_metadata(&metadata);
+    let validator_headers = validator.get_headers();
+    match evaluate_preconditions(headers, &validator) {
+        Precondition::Passed => {}
+        Precondition::NotModified => {
+            return (StatusCode::NOT_MODIFIED, validator_headers).into_response()
+        }
+        Precondition::Failed => return StatusCode::PRECONDITION_FAILED.into_response(),
+    }
+    let range_allowed = is_range_allowed(headers, &validator);
+    let response = build_file_response(
+        full_path,
+        file,
+        headers,
+        metadata.len(),
+        range_allowed,
+        disposition,
+    )
+    .await;
+    (validator_headers, response).into_response()
+}
+
+async fn build_file_response(
+    full_path: &Path,
+    file: File,
+    headers: &HeaderMap,
+    length: u64,
+    range_allowed: bool,
+    disposition: String,
+) -> Response {
     // handle range error
-    let length = unwrap_result_or_return!(full_path.metadata()).len();
-    trace!("serve_fs_files(), file size={}", length);
-    let parse_range_result_option = headers
+    trace!("build_file_response(), file size={}", length);
+    let ranges = headers
         .get(RANGE)
+        .filter(|_| range_allowed)
         .and_then(|value| value.to_str().ok())
-        .map(|value| {
-            http_range_header::parse_range_header(value)
-                .and_then(|first_pass| first_pass.validate(length))
-        });
-    match parse_range_result_option {
-        Some(Ok(ranges)) => {
-            let only_one_range = ranges.len() == 1;
-            match (ranges.first(), only_one_range) {
-                (Some(range), true) => {
-                    return build_range_response(&full_path, range).await;
-                }
-                _ => {
-                    return build_range_error_response(length);
-                }
-            }
-        }
-        Some(Err(_)) => {
-            return build_range_error_response(length);
+        .and_then(|value| parse_ranges(value, length));
+    match ranges {
+        Some(ranges) if ranges.is_empty() => return build_range_error_response(length),
+        Some(ranges) if ranges.len() == 1 => {
+            return build_range_response(full_path, file, &ranges[0], length).await
         }
+        Some(ranges) => return build_multi_range_response(full_path, file, ranges, length).await,
         None => {} // return full body
     };
     // read file and setup response as stream, refer to https://github.com/tokio-rs/axum/discussions/608
-    let file = match tokio::fs::File::open(&full_path).await {
-        Ok(file) => file,
-        Err(_) => return StatusCode::NOT_FOUND.into_response(),
-    };
-    let mime = mime_guess::from_path(&full_path).first_or_octet_stream();
-    let disposition = format!(
-        "attachment; filename={:?}",
-        full_path.file_name().unwrap_or_default()
-    );
+    let file = tokio::fs::File::from_std(file);
+    let mime = mime_guess::from_path(full_path).first_or_octet_stream();
     trace!("disposition={}", disposition);
     let body = StreamBody::new(ReaderStream::new(file));
     let headers = AppendHeaders([
@@ -206,22 +530,28 @@ async fn serve_fs_files(path: &Path, headers: HeaderMap, config: ExtConfig) -> R
 
 async fn serve_files(
     AxumPath(path): AxumPath<String>,
+    RawQuery(query): RawQuery,
     headers: HeaderMap,
     config: ExtConfig,
+    Extension(user): ExtUser,
 ) -> Response {
     trace!("serve_files(), path=={:?}", path);
     let url_path = PathBuf::from(path);
 
     // only test file path with prefix when download is allowed
-    if config.allow_download {
+    if user.permission.download {
         // when path in url starts with prefix, return corresponding fs file
         if let Ok(fs_file_path) = url_path.strip_prefix(&config.prefix) {
-            if fs_file_path.as_os_str().is_empty() {
-                // no fs file path specified means file not found
+            let archive_query = query
+                .as_deref()
+                .and_then(|query| FsQuery::parse(Some(query)).ok())
+                .is_some_and(|query| query.archive.is_some());
+            if fs_file_path.as_os_str().is_empty() && !archive_query {
+                // no fs file path specified means file not found, unless root is archived
                 trace!("serve_files(), prefix only path, return 404");
                 return StatusCode::NOT_FOUND.into_response();
             }
-            return serve_fs_files(fs_file_path, headers, config).await;
+            return serve_fs_files(fs_file_path, query.as_deref(), headers, &config, &user).await;
         }
     }
 
@@ -232,18 +562,169 @@ async fn serve_files(
             trace!("serve_files(), empty path, redirect to /index.html");
             return Redirect::permanent("/index.html").into_response();
         }
-        return serve_embedded_files(embedded_file_path).await;
+        return serve_embedded_files(embedded_file_path, &headers).await;
     }
 
     // shouldn't be here, but who knows
     StatusCode::BAD_REQUEST.into_response()
 }
 
+async fn receive_body_stream(
+    mut body: BodyStream,
+    writer: &mut UploadFileWriter,
+) -> Result<(), AnyError> {
+    while let Some(data) = body.next().await {
+        writer.write(&data?).await?;
+    }
+    Ok(())
+}
+
+async fn put_files(
+    AxumPath(path): AxumPath<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    config: ExtConfig,
+    Extension(user): ExtUser,
+    body: BodyStream,
+) -> Response {
+    trace!("put_files(), user={}, path=={:?}", user.name, path);
+    if !user.permission.upload {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // existing file is kept by default, overwriting must be asked for like over gRPC
+    let query = unwrap_result_or_return!(FsQuery::parse(query.as_deref()), StatusCode::BAD_REQUEST);
+    let on_conflict = query.on_conflict.unwrap_or_default();
+
+    // only fs file path with prefix could be uploaded
+    let url_path = PathBuf::from(path);
+    let fs_file_path =
+        unwrap_result_or_return!(url_path.strip_prefix(&config.prefix), StatusCode::NOT_FOUND);
+    // bare prefix has no file name to upload to
+    let dir_path = unwrap_option_or_return!(
+        fs_file_path.parent().and_then(|p| p.to_str()),
+        StatusCode::BAD_REQUEST
+    );
+    let file_name = unwrap_option_or_return!(
+        fs_file_path.file_name().and_then(|n| n.to_str()),
+        StatusCode::BAD_REQUEST
+    );
+    let (_, target_path_name) = unwrap_result_or_return!(get_upload_path_names(
+        &user.root,
+        dir_path,
+        file_name,
+        config.symlinks
+    ));
+    debug!("put_files(), target_path_name={:?}", &target_path_name);
+    let root = user.root.clone();
+    let upload = match unwrap_result_or_return!(
+        config
+            .io_pool
+            .run(move || UploadTarget::start(&root, &target_path_name, on_conflict))
+            .await
+    ) {
+        Some(upload) => upload,
+        // skipped by conflict policy, the existing file is kept
+        None => return StatusCode::NO_CONTENT.into_response(),
+    };
+
+    // stream body to temp file, then rename it to target
+    let file_size = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let result = async {
+        let mut writer = UploadFileWriter::new(upload.clone(), file_size).await?;
+        receive_body_stream(body, &mut writer).await?;
+        writer.finish("", &config.io_pool).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = config
+            .io_pool
+            .run(move || {
+                upload.abort();
+                Ok(())
+            })
+            .await;
+    }
+    unwrap_result_or_return!(result);
+    StatusCode::CREATED.into_response()
+}
+
+async fn post_files(
+    AxumPath(path): AxumPath<String>,
+    RawQuery(query): RawQuery,
+    config: ExtConfig,
+    Extension(user): ExtUser,
+    mut multipart: Multipart,
+) -> Response {
+    trace!("post_files(), user={}, path=={:?}", user.name, path);
+    if !user.permission.upload {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let query = unwrap_result_or_return!(FsQuery::parse(query.as_deref()), StatusCode::BAD_REQUEST);
+    let on_conflict = query.on_conflict.unwrap_or_default();
+
+    // files in multipart are uploaded to the dir specified by path
+    let url_path = PathBuf::from(path);
+    let fs_dir_path =
+        unwrap_result_or_return!(url_path.strip_prefix(&config.prefix), StatusCode::NOT_FOUND);
+    let dir_path = unwrap_option_or_return!(fs_dir_path.to_str(), StatusCode::BAD_REQUEST);
+    while let Some(mut field) =
+        unwrap_result_or_return!(multipart.next_field().await, StatusCode::BAD_REQUEST)
+    {
+        // skip fields which are not files
+        let file_name = match field.file_name() {
+            Some(file_name) => file_name.to_string(),
+            None => continue,
+        };
+        let (_, target_path_name) = unwrap_result_or_return!(get_upload_path_names(
+            &user.root,
+            dir_path,
+            &file_name,
+            config.symlinks
+        ));
+        debug!("post_files(), target_path_name={:?}", &target_path_name);
+        let root = user.root.clone();
+        let upload = match unwrap_result_or_return!(
+            config
+                .io_pool
+                .run(move || UploadTarget::start(&root, &target_path_name, on_conflict))
+                .await
+        ) {
+            Some(upload) => upload,
+            // skipped by conflict policy, the rest of files are still uploaded
+            None => continue,
+        };
+        let result = async {
+            let mut writer = UploadFileWriter::new(upload.clone(), None).await?;
+            while let Some(data) = field.chunk().await? {
+                writer.write(&data).await?;
+            }
+            writer.finish("", &config.io_pool).await
+        }
+        .await;
+        if result.is_err() {
+            let _ = config
+                .io_pool
+                .run(move || {
+                    upload.abort();
+                    Ok(())
+                })
+                .await;
+        }
+        unwrap_result_or_return!(result);
+    }
+    StatusCode::CREATED.into_response()
+}
+
 pub fn get_serve_file_service(server_info: &ServerInfo) -> Router<hyper::Body> {
     let config = Config::from(server_info);
     let allow_cors = config.allow_cors;
     let mut app = Router::new()
-        .route("/*path", get(serve_files))
+        .route("/*path", get(serve_files).put(put_files).post(post_files))
         .layer(Extension(Arc::new(config)));
     if allow_cors {
         app = app.layer(CorsLayer::permissive());
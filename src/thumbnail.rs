@@ -0,0 +1,193 @@
+use anyhow::anyhow;
+use filetime::FileTime;
+use image::{imageops::FilterType, io::Limits, ImageFormat};
+use log::{debug, trace};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, Metadata},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+type AnyError = anyhow::Error;
+
+const THUMBNAIL_DIR: &str = "thumbnails";
+const MAX_THUMBNAIL_SIDE: u32 = 1024;
+// decoding huge images costs too much memory, so they have no thumbnail
+const MAX_SOURCE_SIZE: u64 = 64 * 1024 * 1024;
+// small files could still claim huge dimensions, so decoding is limited as well
+const MAX_DECODE_SIDE: u32 = 16 * 1024;
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+/// Workers decoding images at once, each might take up to MAX_DECODE_ALLOC of memory
+pub const THUMBNAIL_WORKERS: usize = 4;
+static TEMP_FILE_ID: AtomicU64 = AtomicU64::new(0);
+// cache is trimmed to the size, and thumbnails unused for the age are dropped,
+// checked once every EVICT_INTERVAL thumbnails generated
+const MAX_CACHE_SIZE: u64 = 256 * 1024 * 1024;
+const MAX_CACHE_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const EVICT_INTERVAL: u64 = 64;
+static GENERATED_COUNT: AtomicU64 = AtomicU64::new(0);
+const SUPPORTED_FORMATS: [ImageFormat; 5] = [
+    ImageFormat::Bmp,
+    ImageFormat::Gif,
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::WebP,
+];
+
+/// Get the default cache dir of thumbnails
+pub fn get_cache_dir() -> Result<PathBuf, AnyError> {
+    #[cfg(target_os = "windows")]
+    let base = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+    #[cfg(not(target_os = "windows"))]
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")));
+    let base = base.ok_or_else(|| anyhow!("Cannot find cache dir"))?;
+    Ok(base.join(env!("CARGO_PKG_NAME")).join(THUMBNAIL_DIR))
+}
+
+/// Whether thumbnail could be generated for the file, by its extension and size
+pub fn has_thumbnail(path: &Path, metadata: &Metadata) -> bool {
+    metadata.is_file()
+        && metadata.len() <= MAX_SOURCE_SIZE
+        && ImageFormat::from_path(path)
+            .map(|format| SUPPORTED_FORMATS.contains(&format))
+            .unwrap_or(false)
+}
+
+/// Parse size of thumbnail in format WxH, e.g. 256x256
+pub fn parse_thumbnail_size(value: &str) -> Result<(u32, u32), AnyError> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or_else(|| anyhow!("thumbnail size should be in format WxH"))?;
+    let (width, height) = (width.parse::<u32>()?, height.parse::<u32>()?);
+    if width == 0 || height == 0 || width > MAX_THUMBNAIL_SIDE || height > MAX_THUMBNAIL_SIDE {
+        return Err(anyhow!(
+            "thumbnail size {}x{} is out of range",
+            width,
+            height
+        ));
+    }
+    Ok((width, height))
+}
+
+// cached thumbnail is outdated automatically when the source file changes
+fn get_cache_key(path: &Path, metadata: &Metadata, width: u32, height: u32) -> String {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let key = format!(
+        "{}\n{}\n{}\n{}x{}",
+        path.to_string_lossy(),
+        mtime.as_nanos(),
+        metadata.len(),
+        width,
+        height
+    );
+    format!("{:x}", Sha256::digest(key))
+}
+
+/// Get the cached thumbnail of image file, which fits in width and height and keeps aspect ratio.
+/// Images with alpha channel are encoded as png, and others as jpeg.
+/// The file is opened by caller, so it's the one checked against root.
+pub fn get_thumbnail(
+    cache_dir: &Path,
+    path: &Path,
+    file: File,
+    width: u32,
+    height: u32,
+) -> Result<PathBuf, AnyError> {
+    let metadata = file.metadata()?;
+    if !has_thumbnail(path, &metadata) {
+        return Err(anyhow!("no thumbnail for {:?}", path));
+    }
+    let key = get_cache_key(path, &metadata, width, height);
+    for extension in ["jpg", "png"] {
+        let thumbnail_path = cache_dir.join(format!("{}.{}", key, extension));
+        if thumbnail_path.is_file() {
+            trace!("get_thumbnail(), cached {:?}", &thumbnail_path);
+            // mtime is the last used time, so recently used ones are kept on eviction
+            let _ = filetime::set_file_mtime(&thumbnail_path, FileTime::now());
+            return Ok(thumbnail_path);
+        }
+    }
+
+    debug!("get_thumbnail(), generate for {:?}", path);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DECODE_SIDE);
+    limits.max_image_height = Some(MAX_DECODE_SIDE);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = image::io::Reader::new(BufReader::new(file));
+    reader.set_format(ImageFormat::from_path(path)?);
+    reader.limits(limits);
+    let image = reader.decode()?;
+    let (extension, format) = match image.color().has_alpha() {
+        true => ("png", ImageFormat::Png),
+        false => ("jpg", ImageFormat::Jpeg),
+    };
+    let thumbnail = match (image.width() <= width, image.height() <= height) {
+        (true, true) => image,
+        _ => image.resize(width, height, FilterType::Triangle),
+    };
+    let thumbnail = match format {
+        ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+        _ => thumbnail,
+    };
+    // write to temp file then rename, so concurrent requests never read partial files
+    std::fs::create_dir_all(cache_dir)?;
+    let thumbnail_path = cache_dir.join(format!("{}.{}", key, extension));
+    let temp_path = cache_dir.join(format!(
+        "{}.{}.tmp.{}",
+        key,
+        TEMP_FILE_ID.fetch_add(1, Ordering::Relaxed),
+        extension
+    ));
+    if let Err(e) = thumbnail.save_with_format(&temp_path, format) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    std::fs::rename(&temp_path, &thumbnail_path)?;
+    if GENERATED_COUNT
+        .fetch_add(1, Ordering::Relaxed)
+        .is_multiple_of(EVICT_INTERVAL)
+    {
+        if let Err(e) = evict_cache(cache_dir, &thumbnail_path) {
+            debug!("get_thumbnail(), failed to evict cache, error={:?}", e);
+        }
+    }
+    Ok(thumbnail_path)
+}
+
+// remove outdated and least recently used thumbnails, temp files are only removed when expired
+fn evict_cache(cache_dir: &Path, keep: &Path) -> Result<(), AnyError> {
+    let now = SystemTime::now();
+    let mut files = vec![];
+    for entry in std::fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            let mtime = metadata.modified().unwrap_or(now);
+            files.push((mtime, metadata.len(), entry.path()));
+        }
+    }
+    files.sort();
+    let mut total_size: u64 = files.iter().map(|(_, size, _)| size).sum();
+    for (mtime, size, path) in files {
+        let expired = now.duration_since(mtime).unwrap_or_default() > MAX_CACHE_AGE;
+        let is_temp = path.to_string_lossy().contains(".tmp.");
+        let oversized = total_size > MAX_CACHE_SIZE && !is_temp;
+        if path == keep || !(expired || oversized) {
+            continue;
+        }
+        trace!("evict_cache(), remove {:?}", &path);
+        if std::fs::remove_file(&path).is_ok() {
+            total_size -= size;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use anyhow::anyhow;
+use log::{debug, trace};
+use sha2::{Digest, Sha256};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+type AnyError = anyhow::Error;
+
+const SELF_SIGNED_CERT_FILE: &str = "self-signed.cert.pem";
+const SELF_SIGNED_KEY_FILE: &str = "self-signed.key.pem";
+const SELF_SIGNED_NAMES_FILE: &str = "self-signed.names";
+
+fn get_config_dir() -> Result<PathBuf, AnyError> {
+    #[cfg(target_os = "windows")]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(target_os = "windows"))]
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let base = base.ok_or_else(|| anyhow!("Cannot find config dir"))?;
+    Ok(base.join(env!("CARGO_PKG_NAME")))
+}
+
+// config dir is private to the user, since it holds the private key
+fn create_config_dir(config_dir: &Path) -> Result<(), AnyError> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(not(target_os = "windows"))]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        builder.mode(0o700);
+        builder.create(config_dir)?;
+        // mode is not applied to existing dir
+        std::fs::set_permissions(config_dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    #[cfg(target_os = "windows")]
+    builder.create(config_dir)?;
+    Ok(())
+}
+
+// private key is readable by the user only, it's never created with default umask
+fn write_private_file(path: &Path, contents: &str) -> Result<(), AnyError> {
+    use std::io::Write;
+    // mode is not applied to existing file, so it's removed first
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(not(target_os = "windows"))]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+fn get_subject_alt_names(available_ip: &[IpAddr]) -> Vec<String> {
+    let mut names = vec!["localhost".to_string()];
+    names.extend(available_ip.iter().map(|ip| ip.to_string()));
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Get cert and key files of the self-signed certificate covering all available ip,
+/// cached in the config dir, and the cached one is reused unless the ip changed
+pub fn get_self_signed_files(
+    config_dir: Option<&Path>,
+    available_ip: &[IpAddr],
+) -> Result<(PathBuf, PathBuf), AnyError> {
+    let config_dir = match config_dir {
+        Some(config_dir) => config_dir.to_path_buf(),
+        None => get_config_dir()?,
+    };
+    let cert_path = config_dir.join(SELF_SIGNED_CERT_FILE);
+    let key_path = config_dir.join(SELF_SIGNED_KEY_FILE);
+    let names_path = config_dir.join(SELF_SIGNED_NAMES_FILE);
+    let names = get_subject_alt_names(available_ip).join("\n");
+    trace!("get_self_signed_files(), names={:?}", names);
+
+    // reuse cached certificate when it covers the same names
+    let cached_names = std::fs::read_to_string(&names_path).unwrap_or_default();
+    if cached_names == names && cert_path.is_file() && key_path.is_file() {
+        debug!("get_self_signed_files(), reuse cached {:?}", &cert_path);
+        return Ok((cert_path, key_path));
+    }
+
+    debug!("get_self_signed_files(), generate into {:?}", &config_dir);
+    let cert = rcgen::generate_simple_self_signed(get_subject_alt_names(available_ip))?;
+    create_config_dir(&config_dir)?;
+    std::fs::write(&cert_path, cert.serialize_pem()?)?;
+    write_private_file(&key_path, &cert.serialize_private_key_pem())?;
+    std::fs::write(&names_path, names)?;
+    Ok((cert_path, key_path))
+}
+
+/// Get sha256 fingerprint of the first certificate in pem file, in format like AB:CD:...
+pub fn get_fingerprint(cert_path: &Path) -> Result<String, AnyError> {
+    let pem = std::fs::read_to_string(cert_path)?;
+    let base64_content: String = pem
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN CERTIFICATE-----"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END CERTIFICATE-----"))
+        .collect();
+    if base64_content.is_empty() {
+        return Err(anyhow!("No certificate found in {:?}", cert_path));
+    }
+    let der = base64::decode(base64_content)?;
+    let fingerprint = Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(":");
+    Ok(fingerprint)
+}
@@ -0,0 +1,295 @@
+use crate::{
+    conflict::{remove_existing, resolve_conflict, ConflictPolicy},
+    manage::move_entry,
+    resolve::{get_names, resolve_path_from, validate_name, SymlinkPolicy},
+};
+use anyhow::anyhow;
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::{self, ErrorKind, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+type AnyError = anyhow::Error;
+
+/// Hidden dir under root keeping deleted items, it's never listed or searched
+pub const TRASH_DIR_NAME: &str = ".serva-trash";
+// deleted items are kept in files, and their info in info, like trash of freedesktop
+const FILES_DIR_NAME: &str = "files";
+const INFO_DIR_NAME: &str = "info";
+const INFO_FILE_SUFFIX: &str = "json";
+// expired items are purged in this interval
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Whether the path is the trash dir or inside it
+pub fn is_in_trash(path: &Path) -> bool {
+    path.components()
+        .any(|component| matches!(component, Component::Normal(name) if name == TRASH_DIR_NAME))
+}
+
+fn get_now_in_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn get_size(path: &Path) -> u64 {
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => fs_extra::dir::get_size(path).unwrap_or_default(),
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => 0,
+    }
+}
+
+// names which are not utf-8 could not be saved and restored exactly, so they are refused
+fn get_original_path(path: &Path) -> Result<String, AnyError> {
+    let original_path = path.to_str().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} is not valid utf-8, it could not be deleted", path),
+        )
+    })?;
+    Ok(original_path.to_string())
+}
+
+/// Info of deleted item, saved as json beside it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashInfo {
+    /// Path relative to root before deleted
+    pub original_path: String,
+    pub deleted_timestamp_in_ms: i64,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TrashItem {
+    pub id: String,
+    pub info: TrashInfo,
+}
+
+/// Trash under root, every root of users has its own trash
+#[derive(Debug)]
+pub struct Trash {
+    root: PathBuf,
+    dir: PathBuf,
+}
+
+impl Trash {
+    pub fn new(root: &Path) -> Self {
+        Trash {
+            root: root.to_path_buf(),
+            dir: root.join(TRASH_DIR_NAME),
+        }
+    }
+
+    fn get_item_path(&self, id: &str) -> PathBuf {
+        self.dir.join(FILES_DIR_NAME).join(id)
+    }
+
+    fn get_info_path(&self, id: &str) -> PathBuf {
+        self.dir
+            .join(INFO_DIR_NAME)
+            .join(format!("{}.{}", id, INFO_FILE_SUFFIX))
+    }
+
+    // id is the timestamp with a sequence, reserved by creating its info file
+    fn create_info_file(&self, now: i64) -> Result<(String, std::fs::File), AnyError> {
+        for sequence in 0.. {
+            let id = format!("{}-{}", now, sequence);
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.get_info_path(&id))
+            {
+                Ok(file) => return Ok((id, file)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(anyhow!("no available trash id"))
+    }
+
+    /// Move entry at path into trash, symlinks are moved themselves.
+    /// Entries on other filesystems under root are copied into trash and then removed.
+    pub fn move_to_trash(&self, path: &Path) -> Result<String, AnyError> {
+        let original_path = get_original_path(
+            path.strip_prefix(&self.root)
+                .map_err(|_| anyhow!("{:?} is not under root", path))?,
+        )?;
+        std::fs::create_dir_all(self.dir.join(FILES_DIR_NAME))?;
+        std::fs::create_dir_all(self.dir.join(INFO_DIR_NAME))?;
+        let info = TrashInfo {
+            original_path,
+            deleted_timestamp_in_ms: get_now_in_ms(),
+            is_dir: path.symlink_metadata()?.is_dir(),
+            size: get_size(path),
+        };
+        let (id, mut info_file) = self.create_info_file(info.deleted_timestamp_in_ms)?;
+        trace!("move_to_trash(), path={:?}, id={}", path, &id);
+        let result = serde_json::to_writer(&mut info_file, &info)
+            .map_err(AnyError::from)
+            .and_then(|_| Ok(info_file.flush()?))
+            .and_then(|_| move_entry(path, &self.get_item_path(&id), &mut |_| true));
+        if result.is_err() {
+            let _ = std::fs::remove_file(self.get_info_path(&id));
+        }
+        result.map(|_| id)
+    }
+
+    /// Replace target with source, existing target is moved into trash instead of being removed,
+    /// and it's put back when moving source fails
+    pub fn replace(&self, source: &Path, target: &Path) -> Result<(), AnyError> {
+        if target.symlink_metadata().is_err() {
+            return move_entry(source, target, &mut |_| true);
+        }
+        let id = self.move_to_trash(target)?;
+        debug!("replace(), target={:?} moved to trash, id={}", target, &id);
+        if let Err(e) = move_entry(source, target, &mut |_| true) {
+            if move_entry(&self.get_item_path(&id), target, &mut |_| true).is_ok() {
+                let _ = std::fs::remove_file(self.get_info_path(&id));
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn get_item(&self, id: &str) -> Result<TrashItem, AnyError> {
+        validate_name(id)?;
+        let data = std::fs::read(self.get_info_path(id)).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => {
+                io::Error::new(ErrorKind::NotFound, format!("trash item {} not found", id))
+            }
+            _ => e,
+        })?;
+        Ok(TrashItem {
+            id: id.to_string(),
+            info: serde_json::from_slice(&data)?,
+        })
+    }
+
+    /// List items in trash, newest first, items with broken info are skipped
+    pub fn list(&self) -> Result<Vec<TrashItem>, AnyError> {
+        let entries = match std::fs::read_dir(self.dir.join(INFO_DIR_NAME)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut items = vec![];
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let id = match path.file_stem() {
+                Some(id) if path.extension().is_some_and(|ext| ext == INFO_FILE_SUFFIX) => {
+                    id.to_string_lossy().to_string()
+                }
+                _ => continue,
+            };
+            match self.get_item(&id) {
+                Ok(item) => items.push(item),
+                Err(e) => trace!("list(), skip {}, error={:?}", &id, e),
+            }
+        }
+        items.sort_by(|a, b| {
+            b.info
+                .deleted_timestamp_in_ms
+                .cmp(&a.info.deleted_timestamp_in_ms)
+                .then_with(|| b.id.cmp(&a.id))
+        });
+        Ok(items)
+    }
+
+    // get parent dir of original path, missing dirs are created again
+    fn get_restore_dir(
+        &self,
+        names: &[&str],
+        symlinks: SymlinkPolicy,
+    ) -> Result<PathBuf, AnyError> {
+        let mut dir = self.root.clone();
+        for name in names {
+            if dir.join(name).symlink_metadata().is_err() {
+                std::fs::create_dir(dir.join(name))?;
+            }
+            dir = resolve_path_from(&self.root, &dir, name, symlinks)?;
+        }
+        Ok(dir)
+    }
+
+    /// Restore item to its original path, None means it's skipped by on_conflict and kept in trash
+    pub fn restore(
+        &self,
+        id: &str,
+        symlinks: SymlinkPolicy,
+        on_conflict: ConflictPolicy,
+    ) -> Result<Option<PathBuf>, AnyError> {
+        let item = self.get_item(id)?;
+        let names = get_names(&item.info.original_path)?;
+        let (name, parent_names) = names
+            .split_last()
+            .ok_or_else(|| anyhow!("invalid original path of trash item {}", id))?;
+        let dir = self.get_restore_dir(parent_names, symlinks)?;
+        let target = match resolve_conflict(&dir.join(name), on_conflict)? {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        trace!("restore(), id={}, target={:?}", id, &target);
+        // existing target is moved into trash when overwritten, and kept when restoring fails
+        self.replace(&self.get_item_path(id), &target)?;
+        std::fs::remove_file(self.get_info_path(id))?;
+        Ok(Some(target))
+    }
+
+    fn remove(&self, id: &str) -> Result<(), AnyError> {
+        trace!("remove(), id={}", id);
+        remove_existing(&self.get_item_path(id))?;
+        std::fs::remove_file(self.get_info_path(id))?;
+        Ok(())
+    }
+
+    /// Remove items permanently, all items are removed when ids is empty
+    pub fn empty(&self, ids: &[String]) -> Result<u64, AnyError> {
+        let ids = match ids.is_empty() {
+            true => self.list()?.into_iter().map(|item| item.id).collect(),
+            false => ids.to_vec(),
+        };
+        // all ids are checked before removing any of them
+        for id in &ids {
+            self.get_item(id)?;
+        }
+        for id in &ids {
+            self.remove(id)?;
+        }
+        Ok(ids.len() as u64)
+    }
+
+    /// Remove items deleted before retention
+    pub fn purge(&self, retention: Duration) -> Result<u64, AnyError> {
+        let expired = get_now_in_ms() - retention.as_millis() as i64;
+        let mut count = 0;
+        for item in self.list()? {
+            if item.info.deleted_timestamp_in_ms < expired {
+                self.remove(&item.id)?;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            debug!("purge(), trash={:?}, count={}", &self.dir, count);
+        }
+        Ok(count)
+    }
+}
+
+/// Purge expired items of trash under roots in background, once at start and then periodically
+pub fn start_purging(roots: Vec<PathBuf>, retention: Duration) {
+    std::thread::spawn(move || loop {
+        for root in &roots {
+            if let Err(e) = Trash::new(root).purge(retention) {
+                debug!("start_purging(), root={:?}, error={:?}", root, e);
+            }
+        }
+        std::thread::sleep(PURGE_INTERVAL);
+    });
+}
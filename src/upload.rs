@@ -0,0 +1,268 @@
+use crate::{
+    conflict::{get_temp_path, resolve_conflict, ConflictPolicy},
+    pool::FsPool,
+    resolve::{resolve_new_path, resolve_path, SymlinkPolicy},
+    trash::Trash,
+};
+use anyhow::anyhow;
+use log::trace;
+use sha2::{Digest, Sha256};
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::prelude::FileExt;
+#[cfg(target_os = "windows")]
+use std::os::windows::fs::FileExt;
+use std::{
+    fmt::{Display, Formatter},
+    fs::remove_file,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+
+type AnyError = anyhow::Error;
+
+const UPLOAD_FILE_SUFFIX: &str = "uploading";
+
+#[derive(Debug)]
+pub struct HashMismatchError {
+    target: &'static str,
+    expected: String,
+    actual: String,
+}
+
+impl Display for HashMismatchError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hash mismatch, expected={}, actual={}",
+            self.target, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for HashMismatchError {}
+
+pub fn get_sha256_of_data(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+pub fn get_sha256_of_file(path: &Path) -> Result<String, AnyError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn verify_hash<F>(target: &'static str, expected: &str, get_actual: F) -> Result<(), AnyError>
+where
+    F: FnOnce() -> Result<String, AnyError>,
+{
+    // empty hash means client doesn't ask for verification
+    if expected.is_empty() {
+        return Ok(());
+    }
+    let actual = get_actual()?;
+    trace!("verify_hash(), target={}, actual={}", target, actual);
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(HashMismatchError {
+            target,
+            expected: expected.to_string(),
+            actual,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+pub fn get_upload_path_names(
+    root: &Path,
+    dir_path: &str,
+    file_name: &str,
+    symlinks: SymlinkPolicy,
+) -> Result<(PathBuf, PathBuf), AnyError> {
+    // get valid full_path for directory only
+    let full_path = resolve_path(root, dir_path, symlinks)?;
+    // get the full path name of the file to be uploaded, file_name is validated here
+    let target_path_name = resolve_new_path(root, dir_path, file_name, symlinks)?;
+    Ok((full_path, target_path_name))
+}
+
+/// Target of upload after resolving conflict, with the temp file receiving data,
+/// the temp file has a random name and is created exclusively, so nothing else is ever removed
+#[derive(Debug, Clone)]
+pub struct UploadTarget {
+    // existing target is moved into trash under root when replaced
+    root: PathBuf,
+    pub target: PathBuf,
+    pub temp: PathBuf,
+    // an empty target file is created for new target, so the name is reserved while uploading
+    placeholder: bool,
+}
+
+impl UploadTarget {
+    /// Start upload to target, None means the upload should be skipped by on_conflict
+    pub fn start(
+        root: &Path,
+        target: &Path,
+        on_conflict: ConflictPolicy,
+    ) -> Result<Option<Self>, AnyError> {
+        let target = match resolve_conflict(target, on_conflict)? {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        trace!("UploadTarget::start(), target={:?}", &target);
+        let placeholder = match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&target)
+        {
+            Ok(_) => true,
+            // existing target is replaced only when finished
+            Err(e)
+                if e.kind() == ErrorKind::AlreadyExists
+                    && on_conflict == ConflictPolicy::Overwrite =>
+            {
+                false
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let result = get_temp_path(&target, UPLOAD_FILE_SUFFIX).and_then(|temp| {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)?;
+            Ok(temp)
+        });
+        let temp = match result {
+            Ok(temp) => temp,
+            Err(e) => {
+                if placeholder {
+                    let _ = remove_file(&target);
+                }
+                return Err(e);
+            }
+        };
+        Ok(Some(UploadTarget {
+            root: root.to_path_buf(),
+            target,
+            temp,
+            placeholder,
+        }))
+    }
+
+    /// Replace target with temp file, existing target is moved into trash and kept when it fails
+    fn replace_target(&self) -> Result<(), AnyError> {
+        match self.placeholder {
+            true => Ok(std::fs::rename(&self.temp, &self.target)?),
+            false => Trash::new(&self.root).replace(&self.temp, &self.target),
+        }
+    }
+
+    pub fn finish(&self, file_hash: &str) -> Result<(), AnyError> {
+        trace!("UploadTarget::finish(), target={:?}", &self.target);
+        verify_hash("file", file_hash, || get_sha256_of_file(&self.temp))?;
+        self.replace_target()
+    }
+
+    /// Remove temp file, and target only when it's the placeholder, existing target is kept
+    pub fn abort(&self) {
+        trace!("UploadTarget::abort(), target={:?}", &self.target);
+        let _ = remove_file(&self.temp);
+        if self.placeholder {
+            let _ = remove_file(&self.target);
+        }
+    }
+}
+
+pub fn write_chunk(temp: &Path, data: &[u8], offset: u64, file_size: u64) -> Result<(), AnyError> {
+    trace!("write_chunk()");
+    let data_size = data.len();
+    let end = offset.checked_add(data_size as u64);
+    if end.is_none_or(|end| end > file_size) {
+        return Err(anyhow!(
+            "data exceeds file size, offset={}, data_size={}, file_size={}",
+            offset,
+            data_size,
+            file_size
+        ));
+    }
+    let temp_file = std::fs::OpenOptions::new().write(true).open(temp)?;
+    #[cfg(target_os = "windows")]
+    temp_file.seek_write(data, offset)?;
+    #[cfg(not(target_os = "windows"))]
+    temp_file.write_all_at(data, offset)?;
+    temp_file.sync_all()?;
+    Ok(())
+}
+
+// writer of the streaming upload, keeps the temp file open until all data written
+// file_size is None when the size is unknown before receiving, e.g. multipart upload
+// it writes with tokio::fs, so receiving data never blocks the async runtime
+pub struct UploadFileWriter {
+    upload: UploadTarget,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    file_size: Option<u64>,
+    written_size: u64,
+}
+
+impl UploadFileWriter {
+    pub async fn new(upload: UploadTarget, file_size: Option<u64>) -> Result<Self, AnyError> {
+        trace!("UploadFileWriter::new(), target={:?}", &upload.target);
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&upload.temp)
+            .await?;
+        Ok(UploadFileWriter {
+            upload,
+            file,
+            hasher: Sha256::new(),
+            file_size,
+            written_size: 0,
+        })
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), AnyError> {
+        let data_size = data.len() as u64;
+        match self.file_size {
+            Some(file_size) if self.written_size + data_size > file_size => {
+                return Err(anyhow!(
+                    "data exceeds file size, offset={}, data_size={}, file_size={}",
+                    self.written_size,
+                    data_size,
+                    file_size
+                ));
+            }
+            _ => {}
+        }
+        self.file.write_all(data).await?;
+        self.hasher.update(data);
+        self.written_size += data_size;
+        Ok(())
+    }
+
+    pub async fn finish(self, file_hash: &str, pool: &FsPool) -> Result<u64, AnyError> {
+        trace!(
+            "UploadFileWriter::finish(), written_size={}",
+            self.written_size
+        );
+        match self.file_size {
+            Some(file_size) if self.written_size != file_size => {
+                return Err(anyhow!(
+                    "incomplete upload, written_size={}, file_size={}",
+                    self.written_size,
+                    file_size
+                ));
+            }
+            _ => {}
+        }
+        let hasher = self.hasher;
+        verify_hash("file", file_hash, || Ok(format!("{:x}", hasher.finalize())))?;
+        // sync once for the whole file
+        self.file.sync_all().await?;
+        drop(self.file);
+        let upload = self.upload;
+        pool.run(move || upload.replace_target()).await?;
+        Ok(self.written_size)
+    }
+}
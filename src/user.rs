@@ -0,0 +1,129 @@
+use crate::resolve::{resolve_path, SymlinkPolicy};
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+type AnyError = anyhow::Error;
+
+pub const DEFAULT_USER_NAME: &str = "default";
+pub const ANONYMOUS_USER_NAME: &str = "anonymous";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UserPermission {
+    pub create: bool,
+    pub copy: bool,
+    #[serde(rename = "move")]
+    pub r#move: bool,
+    pub delete: bool,
+    pub rename: bool,
+    pub upload: bool,
+    pub download: bool,
+}
+
+/// Item of the users file, which is a json array of it, or `[[users]]` tables in toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserConfig {
+    pub name: String,
+    pub password: Option<String>,
+    pub token: Option<String>,
+    /// Sub dir of the served dir, which is the root of this user
+    pub root: Option<String>,
+    #[serde(default)]
+    pub permission: UserPermission,
+}
+
+/// User loaded from the users file with credentials
+#[derive(Debug, Clone)]
+pub struct User {
+    pub profile: UserProfile,
+    pub password: Option<String>,
+    pub token: Option<String>,
+}
+
+/// Effective rights of a caller, inserted into request extensions by auth
+#[derive(Debug, Clone)]
+pub struct UserProfile {
+    pub name: String,
+    pub root: PathBuf,
+    pub permission: UserPermission,
+}
+
+impl UserProfile {
+    pub fn new(name: &str, root: &Path, permission: UserPermission) -> Self {
+        UserProfile {
+            name: name.to_string(),
+            root: root.to_path_buf(),
+            permission,
+        }
+    }
+
+    pub fn from_config(
+        config: &UserConfig,
+        root: &Path,
+        symlinks: SymlinkPolicy,
+    ) -> Result<Self, AnyError> {
+        let user_root = match &config.root {
+            Some(sub_dir) => resolve_path(root, sub_dir, symlinks)?,
+            None => root.to_path_buf(),
+        };
+        if !user_root.is_dir() {
+            return Err(anyhow!("root of user {} is not a dir", config.name));
+        }
+        Ok(UserProfile::new(
+            &config.name,
+            &user_root,
+            config.permission.clone(),
+        ))
+    }
+
+    pub fn get_root_string(&self) -> String {
+        self.root.to_string_lossy().to_string()
+    }
+}
+
+// Toml requires a table at top level, so users are listed as `[[users]]`
+#[derive(Deserialize)]
+struct UsersFile {
+    users: Vec<UserConfig>,
+}
+
+fn parse_users(path: &str) -> Result<Vec<UserConfig>, AnyError> {
+    let content = std::fs::read_to_string(path)?;
+    let is_toml = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+    if is_toml {
+        Ok(toml::from_str::<UsersFile>(&content)?.users)
+    } else {
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+pub fn load_users(path: &str, root: &Path, symlinks: SymlinkPolicy) -> Result<Vec<User>, AnyError> {
+    let configs = parse_users(path)?;
+    let mut users = vec![];
+    for config in &configs {
+        if config.name.is_empty() || config.name.contains(':') {
+            return Err(anyhow!("user name {:?} is invalid", config.name));
+        }
+        if config.name == DEFAULT_USER_NAME || config.name == ANONYMOUS_USER_NAME {
+            return Err(anyhow!("user name {} is reserved", config.name));
+        }
+        if config.password.is_none() && config.token.is_none() {
+            return Err(anyhow!(
+                "user {} has neither password nor token",
+                config.name
+            ));
+        }
+        if configs.iter().filter(|c| c.name == config.name).count() > 1 {
+            return Err(anyhow!("user {} is defined more than once", config.name));
+        }
+        users.push(User {
+            profile: UserProfile::from_config(config, root, symlinks)?,
+            password: config.password.clone(),
+            token: config.token.clone(),
+        });
+    }
+    Ok(users)
+}
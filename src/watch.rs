@@ -0,0 +1,248 @@
+use log::{debug, trace};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecursiveMode, Watcher,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::Sender;
+
+type AnyError = anyhow::Error;
+
+// changes are sent after no more change in this interval
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(200);
+// continuous changes are still sent in this interval
+const MAX_DEBOUNCE_DELAY: Duration = Duration::from_secs(1);
+// interval to check whether the receiver is closed when nothing changes
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// each watcher holds a thread and inotify watches, so they are limited
+const MAX_WATCHERS: usize = 256;
+const MAX_WATCHERS_PER_USER: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed(PathBuf),
+}
+
+/// A debounced change of path, Renamed carries the new path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+// merge raw events of a path into changes, e.g. created then deleted files are dropped
+#[derive(Debug, Default)]
+struct ChangeBatch {
+    changes: Vec<Change>,
+}
+
+impl ChangeBatch {
+    fn find(&self, path: &Path) -> Option<usize> {
+        self.changes
+            .iter()
+            .rposition(|change| change.path == path && change.kind != ChangeKind::Deleted)
+    }
+
+    fn add_created(&mut self, path: PathBuf) {
+        // deleted then created again is a modification
+        match self.changes.iter().rposition(|change| change.path == path) {
+            Some(i) if self.changes[i].kind == ChangeKind::Deleted => {
+                self.changes[i].kind = ChangeKind::Modified
+            }
+            _ => self.changes.push(Change {
+                path,
+                kind: ChangeKind::Created,
+            }),
+        }
+    }
+
+    fn add_modified(&mut self, path: PathBuf) {
+        if self.find(&path).is_none() {
+            self.changes.push(Change {
+                path,
+                kind: ChangeKind::Modified,
+            });
+        }
+    }
+
+    fn add_deleted(&mut self, path: PathBuf) {
+        match self.find(&path) {
+            Some(i) if self.changes[i].kind == ChangeKind::Created => {
+                self.changes.remove(i);
+            }
+            Some(i) => self.changes[i].kind = ChangeKind::Deleted,
+            None => self.changes.push(Change {
+                path,
+                kind: ChangeKind::Deleted,
+            }),
+        }
+    }
+
+    // moved from and moved to are reported before the rename with both paths
+    fn add_renamed(&mut self, from: PathBuf, to: PathBuf) {
+        self.changes.retain(|change| {
+            !(change.path == from && change.kind == ChangeKind::Deleted
+                || change.path == to && change.kind == ChangeKind::Created)
+        });
+        self.changes.push(Change {
+            path: from,
+            kind: ChangeKind::Renamed(to),
+        });
+    }
+
+    fn add_event(&mut self, event: notify::Event) {
+        let mut paths = event.paths.into_iter();
+        match event.kind {
+            EventKind::Create(_) => paths.for_each(|path| self.add_created(path)),
+            EventKind::Remove(_) => paths.for_each(|path| self.add_deleted(path)),
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                paths.for_each(|path| self.add_deleted(path))
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                paths.for_each(|path| self.add_created(path))
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let (Some(from), Some(to)) = (paths.next(), paths.next()) {
+                    self.add_renamed(from, to);
+                }
+            }
+            EventKind::Modify(_) => paths.for_each(|path| self.add_modified(path)),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct WatcherCounts {
+    total: usize,
+    owners: HashMap<String, usize>,
+}
+
+/// Count of running watchers, to limit them globally and per user
+#[derive(Debug, Default)]
+pub struct WatcherLimit {
+    counts: Mutex<WatcherCounts>,
+}
+
+/// Slot of a running watcher, released when dropped
+#[derive(Debug)]
+pub struct WatcherSlot {
+    limit: Arc<WatcherLimit>,
+    owner: Option<String>,
+}
+
+impl WatcherLimit {
+    /// Take a slot for owner, None when there are too many watchers.
+    /// Watchers without owner are limited globally only.
+    pub fn acquire(self: &Arc<Self>, owner: Option<&str>) -> Option<WatcherSlot> {
+        let mut counts = self.counts.lock().ok()?;
+        let count = owner.map_or(0, |owner| {
+            counts.owners.get(owner).copied().unwrap_or_default()
+        });
+        if counts.total >= MAX_WATCHERS || count >= MAX_WATCHERS_PER_USER {
+            debug!(
+                "WatcherLimit::acquire(), too many watchers, owner={:?}, count={}, total={}",
+                owner, count, counts.total
+            );
+            return None;
+        }
+        counts.total += 1;
+        if let Some(owner) = owner {
+            *counts.owners.entry(owner.to_string()).or_default() += 1;
+        }
+        Some(WatcherSlot {
+            limit: self.clone(),
+            owner: owner.map(str::to_string),
+        })
+    }
+}
+
+impl Drop for WatcherSlot {
+    fn drop(&mut self) {
+        if let Ok(mut counts) = self.limit.counts.lock() {
+            counts.total -= 1;
+            if let Some(owner) = &self.owner {
+                if let Some(count) = counts.owners.get_mut(owner) {
+                    *count -= 1;
+                    if *count == 0 {
+                        counts.owners.remove(owner);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Watch changes of dir, and send debounced changes in batches until the receiver is closed.
+/// Changes of dir itself are included, and watching stops after dir is deleted or moved.
+/// Slot is held until watching stops.
+pub fn watch_dir(
+    dir: &Path,
+    recursive: bool,
+    sender: Sender<Vec<Change>>,
+    slot: WatcherSlot,
+) -> Result<(), AnyError> {
+    let (event_sender, event_receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(event_sender)?;
+    let mode = match recursive {
+        true => RecursiveMode::Recursive,
+        false => RecursiveMode::NonRecursive,
+    };
+    watcher.watch(dir, mode)?;
+    let dir = dir.to_path_buf();
+    std::thread::spawn(move || {
+        debug!("watch_dir(), start watching {:?}", &dir);
+        // watcher and slot are moved here, and dropped when watching stops
+        let _watcher = watcher;
+        let _slot = slot;
+        let mut batch = ChangeBatch::default();
+        let mut first_change: Option<Instant> = None;
+        loop {
+            let timeout = match first_change {
+                Some(_) => DEBOUNCE_INTERVAL,
+                None => CLOSE_CHECK_INTERVAL,
+            };
+            match event_receiver.recv_timeout(timeout) {
+                Ok(Ok(event)) => {
+                    trace!("watch_dir(), event={:?}", &event);
+                    batch.add_event(event);
+                    first_change.get_or_insert_with(Instant::now);
+                    if !matches!(first_change, Some(first) if first.elapsed() >= MAX_DEBOUNCE_DELAY)
+                    {
+                        continue;
+                    }
+                }
+                Ok(Err(e)) => {
+                    debug!("watch_dir(), error={:?}", e);
+                    continue;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            if sender.is_closed() {
+                break;
+            }
+            first_change = None;
+            let changes = std::mem::take(&mut batch.changes);
+            if changes.is_empty() {
+                continue;
+            }
+            let dir_removed = changes
+                .iter()
+                .any(|change| change.path == dir && change.kind != ChangeKind::Modified);
+            if sender.blocking_send(changes).is_err() || dir_removed {
+                break;
+            }
+        }
+        debug!("watch_dir(), stop watching {:?}", &dir);
+    });
+    Ok(())
+}
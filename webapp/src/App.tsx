@@ -2,7 +2,7 @@ import FileManager, { Permissions } from "devextreme-react/file-manager";
 import React, { createRef } from "react";
 import "./App.css";
 import { DownloadState, EventBus, EventContextDownload, EVENT_DOWNLOAD, ServaFileSystemProvider } from "./FileSystem";
-import { Config, GetConfig, Permission } from "./GrpcClient";
+import { Config, GetConfig, IsUnauthenticated, Login, Permission, WatchDir } from "./GrpcClient";
 // import { Popup } from 'devextreme-react/popup';
 
 const IS_DEVELOPMENT = process.env.NODE_ENV === "development";
@@ -38,6 +38,7 @@ class App extends React.Component<AppProps, AppState> {
     permission: DEFAULT_PERMISSION,
   };
   operation: any;
+  stopWatching: (() => void) | undefined;
   async componentDidMount() {
     //ATTENTION: debug code mixed
     if (IS_DEVELOPMENT) {
@@ -47,9 +48,10 @@ class App extends React.Component<AppProps, AppState> {
     }
     EventBus.on(EVENT_DOWNLOAD, this.onDownloadEvent.bind(this));
     try {
-      let config = await GetConfig();
+      let config = await this.getConfigWithLogin();
       this.setState({ permission: config.permission });
       this.config = config;
+      this.watchDir("");
     } catch (e) {
       if (e instanceof Error) {
         console.log(`failed to get config, error: ${e.message}`);
@@ -57,8 +59,36 @@ class App extends React.Component<AppProps, AppState> {
     }
   }
 
+  async getConfigWithLogin(): Promise<Config> {
+    try {
+      return await GetConfig();
+    } catch (e) {
+      if (!IsUnauthenticated(e)) {
+        throw e;
+      }
+      let credential = window.prompt("Login required, input token or user:password");
+      if (!credential) {
+        throw e;
+      }
+      return await Login(credential);
+    }
+  }
+
   componentWillUnmount() {
     EventBus.removeListener(EVENT_DOWNLOAD);
+    this.stopWatching?.();
+  }
+
+  // refresh when the current dir is changed by others
+  watchDir(dir_path: string) {
+    this.stopWatching?.();
+    this.stopWatching = WatchDir(dir_path, () => {
+      this.fileManagerRef.current?.instance.refresh();
+    });
+  }
+
+  onCurrentDirectoryChanged(e: any) {
+    this.watchDir(e.directory.path);
   }
 
   onDownloadEvent(context: EventContextDownload) {
@@ -95,7 +125,11 @@ class App extends React.Component<AppProps, AppState> {
   render() {
     return (
       <div className="App">
-        <FileManager ref={this.fileManagerRef} fileSystemProvider={fileSystemProvider}>
+        <FileManager
+          ref={this.fileManagerRef}
+          fileSystemProvider={fileSystemProvider}
+          onCurrentDirectoryChanged={this.onCurrentDirectoryChanged.bind(this)}
+        >
           <Permissions {...this.state.permission} />
         </FileManager>
       </div>
@@ -5,12 +5,15 @@ import UploadInfo from 'devextreme/file_management/upload_info';
 import { EventEmitter } from 'eventemitter3';
 // import * from 'devextreme/file_management/utils';
 import {
+  BatchOperation,
+  Config,
   ConvertDirFunction,
   ConvertFileFunction,
   GetConfig,
   ListDir,
   ManageDirOrFile,
   Operation,
+  SubmitJob,
   UploadFileChunk
 } from './GrpcClient';
 
@@ -44,6 +47,7 @@ export enum DownloadState {
 //   Other: 32767
 // Only Other used because backend don't follow these error codes
 const ERROR_OTHER = 32767;
+const THUMBNAIL_SIZE = '128x128';
 
 function getFileSystemErrorFromError(e: unknown, fileSystemItem?: FileSystemItem | undefined) {
   let message = e instanceof Error ? e.message : 'Unknown error';
@@ -83,6 +87,52 @@ async function execAbortFileChunk(dir_path: string, file_name: string, file_size
   await UploadFileChunk(dir_path, file_name, file_size, '', true, new Blob(), 0, 0, 0, 0, '');
 }
 
+type PendingOperation = {
+  operation: BatchOperation;
+  resolve: () => void;
+  reject: (e: unknown) => void;
+};
+
+// file manager calls copyItem, moveItem and deleteItem for every selected item at once,
+// they are collected and submitted as one job, so they are not stopped by closing the page
+class BatchQueue {
+  pending: PendingOperation[] = [];
+
+  execute(operation: BatchOperation): Promise<void> {
+    return new Promise((resolve, reject) => {
+      this.pending.push({ operation, resolve, reject });
+      if (this.pending.length === 1) {
+        setTimeout(() => this.flush(), 0);
+      }
+    });
+  }
+
+  async flush() {
+    let pending = this.pending;
+    this.pending = [];
+    let [done] = SubmitJob(
+      pending.map((item) => item.operation),
+      (index, error) => {
+        if (error === undefined) {
+          pending[index].resolve();
+        } else {
+          pending[index].reject(new Error(error));
+        }
+      }
+    );
+    try {
+      await done;
+      // operations without result are not executed, e.g. the job is cancelled
+      pending.forEach((item) => item.reject(new Error('Not executed')));
+    } catch (e: unknown) {
+      // operations without result are failed by the call
+      pending.forEach((item) => item.reject(e));
+    }
+  }
+}
+
+const batchQueue = new BatchQueue();
+
 // refer to 'CustomFileSystemProviderOptions' in 'devextreme/file_management/custom_provider'
 class AbstractOptions implements Options {
   async abortFileUpload(file: File, uploadInfo: UploadInfo, destinationDirectory: FileSystemItem) {
@@ -161,6 +211,11 @@ class AbstractOptions implements Options {
   }
 }
 
+function getUrlBase(config: Config) {
+  //ATTENTION: debug code mixed
+  return IS_DEVELOPMENT ? (DEBUG_SERVER + config.prefix) : (window.origin + config.prefix);
+}
+
 class ServaFileSystemOptions extends AbstractOptions {
   async downloadItems(items: Array<FileSystemItem>) {
     console.log('downloadItems()');
@@ -177,25 +232,29 @@ class ServaFileSystemOptions extends AbstractOptions {
       throw getFileSystemErrorFromMsg('no permission to download');
     }
 
-    //ATTENTION: debug code mixed
-    let urlbase = IS_DEVELOPMENT ? (DEBUG_SERVER + config.prefix) : (window.origin + config.prefix);
+    let urlbase = getUrlBase(config);
 
     // Since files are shared via GET method, just open a new window to download
-    // When multiple files need to be downloaded, 2nd and later request may very likely be blocked
-    // Refer to https://stackoverflow.com/a/2587692
-    let url_array = items.map((item) => urlbase + item.key);
-    for (let i = 0; i < url_array.length; i++) {
-      let new_window = window.open(url_array[i]);
-      if (new_window) {
-        console.log(`succeeded downloading ${url_array[i]}`);
-        url_array.splice(i, 1);
-        current++;
-      } else {
-        console.log(`failed to download, left url_array=${JSON.stringify(url_array, null, 4)}`);
-        break;
-      }
-      EventBus.emit(EVENT_DOWNLOAD, { state: DownloadState.STEPPING, current, total });
+    // When multiple items or any dir selected, download them in one archive, because 2nd and
+    // later request may very likely be blocked, refer to https://stackoverflow.com/a/2587692
+    let url_array = [urlbase + items[0].key];
+    if (items.length > 1 || items[0].isDirectory) {
+      // selected items are always in the same dir
+      let key = items[0].key;
+      let parent = key.substring(0, Math.max(key.lastIndexOf('/'), 0));
+      let params = new URLSearchParams({ archive: 'zip' });
+      items.forEach((item) => params.append('path', item.name));
+      url_array = [urlbase + parent + '?' + params.toString()];
     }
+    let new_window = window.open(url_array[0]);
+    if (new_window) {
+      console.log(`succeeded downloading ${url_array[0]}`);
+      url_array = [];
+      current = total;
+    } else {
+      console.log(`failed to download, url=${url_array[0]}`);
+    }
+    EventBus.emit(EVENT_DOWNLOAD, { state: DownloadState.STEPPING, current, total });
 
     let succeeded = (url_array.length === 0);
     // send an event of stop downloading
@@ -208,18 +267,25 @@ class ServaFileSystemOptions extends AbstractOptions {
   async getItems(parentDirectory: FileSystemItem) {
     // => PromiseLike<Array<any>> | Array<any>;
     console.log(`getItems(), parentDirectory=${parentDirectory.key}`);
+    // thumbnails are shared via GET method like files, so download permission is required
+    let urlbase = '';
     let fn_dir: ConvertDirFunction = (path, dateModifiedInMs) => {
       let item = new FileSystemItem(path, true);
       item.dateModified = new Date(dateModifiedInMs);
       return item;
     };
-    let fn_file: ConvertFileFunction = (path, dateModifiedInMs, size) => {
+    let fn_file: ConvertFileFunction = (path, dateModifiedInMs, size, hasThumbnail) => {
       let item = new FileSystemItem(path, false);
       item.dateModified = new Date(dateModifiedInMs);
       item.size = size;
+      if (hasThumbnail && urlbase) {
+        item.thumbnail = urlbase + path + '?thumb=' + THUMBNAIL_SIZE;
+      }
       return item;
     };
     try {
+      let config = await GetConfig();
+      urlbase = config.permission.download ? getUrlBase(config) : '';
       return await ListDir(parentDirectory.key, fn_dir, fn_file);
     } catch (e: unknown) {
       throw getFileSystemErrorFromError(e);
@@ -277,7 +343,12 @@ class ServaFileSystemOptions extends AbstractOptions {
     // => PromiseLike<any> | any
     console.log(`copyItem(), item=${item.key}, destinationDirectory=${destinationDirectory.key}`);
     try {
-      await ManageDirOrFile(item.key, destinationDirectory.key, '', Operation.COPY_FILE);
+      await batchQueue.execute({
+        file_name_path: item.key,
+        dir_path: destinationDirectory.key,
+        target: '',
+        operation: Operation.COPY_FILE,
+      });
     } catch (e: unknown) {
       throw getFileSystemErrorFromError(e);
     }
@@ -287,7 +358,12 @@ class ServaFileSystemOptions extends AbstractOptions {
     // => PromiseLike<any> | any
     console.log(`deleteItem(), item=${item.key}`);
     try {
-      await ManageDirOrFile(item.key, '', '', Operation.DELETE_FILE);
+      await batchQueue.execute({
+        file_name_path: item.key,
+        dir_path: '',
+        target: '',
+        operation: Operation.DELETE_FILE,
+      });
     } catch (e: unknown) {
       throw getFileSystemErrorFromError(e);
     }
@@ -297,7 +373,12 @@ class ServaFileSystemOptions extends AbstractOptions {
     // => PromiseLike<any> | any
     console.log(`moveItem(), item=${item.key}, destinationDirectory=${destinationDirectory.key}`);
     try {
-      await ManageDirOrFile(item.key, destinationDirectory.key, '', Operation.MOVE_FILE);
+      await batchQueue.execute({
+        file_name_path: item.key,
+        dir_path: destinationDirectory.key,
+        target: '',
+        operation: Operation.MOVE_FILE,
+      });
     } catch (e: unknown) {
       throw getFileSystemErrorFromError(e);
     }
@@ -1,10 +1,18 @@
+import { StatusCode } from "grpc-web";
 import { ServaManagerClient } from "./generated/ApiServiceClientPb";
 import {
   Address,
+  BatchManageRequest,
+  BatchProgress,
+  ConflictPolicy,
   GetConfigRequest,
+  Job,
   ListDirRequest,
   ManageDirOrFileRequest,
-  UploadFileChunkRequest
+  SubmitJobRequest,
+  UploadFileChunkRequest,
+  WatchDirRequest,
+  WatchJobRequest
 } from "./generated/api_pb.js";
 import Operation = ManageDirOrFileRequest.Operation;
 
@@ -16,7 +24,12 @@ const client = IS_DEVELOPMENT
   : new ServaManagerClient(window.location.origin);
 
 export type ConvertDirFunction = (path: string, dateModified: number) => any;
-export type ConvertFileFunction = (path: string, dateModified: number, size: number) => any;
+export type ConvertFileFunction = (
+  path: string,
+  dateModified: number,
+  size: number,
+  hasThumbnail: boolean
+) => any;
 export type Permission = {
   create: boolean;
   copy: boolean;
@@ -41,18 +54,132 @@ export async function ListDir(
   fn_dir: ConvertDirFunction,
   fn_file: ConvertFileFunction
 ) {
-  let request = new ListDirRequest();
-  request.setDirPath(dir_path);
-  let list_dir_result = await client.listDir(request, null);
-  let directories = list_dir_result
-    .getDirectoriesList()
-    .map((dir) => fn_dir(dir.getPath(), dir.getModifiedTimestampInMs()));
-  let files = list_dir_result
-    .getFilesList()
-    .map((file) => fn_file(file.getPath(), file.getModifiedTimestampInMs(), file.getSize()));
+  let directories = [];
+  let files = [];
+  let page_token = "";
+  // server returns dirs and files page by page
+  do {
+    let request = new ListDirRequest();
+    request.setDirPath(dir_path);
+    request.setPageToken(page_token);
+    let list_dir_result = await client.listDir(request, null);
+    directories.push(
+      ...list_dir_result
+        .getDirectoriesList()
+        .map((dir) => fn_dir(dir.getPath(), dir.getModifiedTimestampInMs()))
+    );
+    files.push(
+      ...list_dir_result
+        .getFilesList()
+        .map((file) =>
+          fn_file(file.getPath(), file.getModifiedTimestampInMs(), file.getSize(), file.getHasThumbnail())
+        )
+    );
+    page_token = list_dir_result.getNextPageToken();
+  } while (page_token);
   return [...directories, ...files];
 }
 
+export type BatchOperation = {
+  file_name_path: string;
+  dir_path: string;
+  target: string;
+  operation: Operation;
+};
+
+function getBatchManageRequest(operations: BatchOperation[]) {
+  let request = new BatchManageRequest();
+  request.setOperationsList(
+    operations.map((operation) => {
+      let item = new ManageDirOrFileRequest();
+      item.setFilePathName(operation.file_name_path);
+      item.setDirPath(operation.dir_path);
+      item.setTarget(operation.target);
+      item.setOperation(operation.operation);
+      return item;
+    })
+  );
+  return request;
+}
+
+// executes operations in one call, on_result gets an error message for failed operations,
+// resolves when all results are received, and the call could be cancelled by the returned function
+export function BatchManage(
+  operations: BatchOperation[],
+  on_result: (index: number, error: string | undefined) => void,
+  on_progress?: (progress: BatchProgress.AsObject) => void
+): [Promise<void>, () => void] {
+  let request = getBatchManageRequest(operations);
+  let stream = client.batchManage(request, null);
+  let done = new Promise<void>((resolve, reject) => {
+    stream.on("data", (response) => {
+      let result = response.getResult();
+      let progress = response.getProgress();
+      if (result) {
+        on_result(result.getIndex(), result.getCode() === 0 ? undefined : result.getMessage());
+      } else if (progress && on_progress) {
+        on_progress(progress.toObject());
+      }
+    });
+    stream.on("error", (e) => reject(e));
+    stream.on("end", () => resolve());
+  });
+  return [done, () => stream.cancel()];
+}
+
+// submits operations as a background job, which keeps running when the page is closed,
+// on_result gets an error message for failed operations like BatchManage,
+// resolves when the job is finished, and watching could be stopped by the returned function
+export function SubmitJob(
+  operations: BatchOperation[],
+  on_result: (index: number, error: string | undefined) => void,
+  on_progress?: (progress: BatchProgress.AsObject) => void
+): [Promise<void>, () => void] {
+  let batch = getBatchManageRequest(operations);
+  let request = new SubmitJobRequest();
+  request.setManage(batch);
+  let cancel = () => {};
+  let done = client.submitJob(request, null).then(
+    (response) =>
+      new Promise<void>((resolve, reject) => {
+        let watch = new WatchJobRequest();
+        watch.setId(response.getId());
+        let stream = client.watchJob(watch, null);
+        cancel = () => stream.cancel();
+        let reported = 0;
+        stream.on("data", (response) => {
+          let job = response.getJob();
+          if (!job) {
+            return;
+          }
+          // results are accumulated in job, only new ones are reported
+          let results = job.getResultsList();
+          results.slice(reported).forEach((result) => {
+            on_result(result.getIndex(), result.getCode() === 0 ? undefined : result.getMessage());
+          });
+          reported = results.length;
+          let progress = job.getProgress();
+          if (progress && on_progress && job.getState() === Job.State.RUNNING) {
+            on_progress(progress.toObject());
+          }
+        });
+        stream.on("error", (e) => reject(e));
+        stream.on("end", () => resolve());
+      })
+  );
+  return [done, () => cancel()];
+}
+
+// calls on_change when something changes in dir, returns a function to stop watching
+export function WatchDir(dir_path: string, on_change: () => void): () => void {
+  let request = new WatchDirRequest();
+  request.setDirPath(dir_path);
+  let stream = client.watchDir(request, null);
+  stream.on("data", () => on_change());
+  stream.on("error", (e) => console.log(`WatchDir(), dir_path=${dir_path}, error=${e.message}`));
+  return () => stream.cancel();
+}
+
 export async function GetConfig(): Promise<Config> {
   if (config) {
     return config;
@@ -82,6 +209,21 @@ export async function GetConfig(): Promise<Config> {
   return config;
 }
 
+export function IsUnauthenticated(e: unknown): boolean {
+  return (e as { code?: number } | undefined)?.code === StatusCode.UNAUTHENTICATED;
+}
+
+// credential is either "user:password" for basic auth or a token,
+// server sets a session cookie once it is accepted
+export async function Login(credential: string): Promise<Config> {
+  let authorization = credential.includes(":")
+    ? `Basic ${btoa(credential)}`
+    : `Bearer ${credential}`;
+  await client.getConfig(new GetConfigRequest(), { authorization });
+  config = undefined;
+  return GetConfig();
+}
+
 export async function UploadFileChunk(
   dir_path: string,
   file_name: string,
@@ -113,6 +255,8 @@ export async function UploadFileChunk(
   request.setChunkOffset(chunk_offset);
   request.setChunkSize(chunk_size);
   request.setChunkHash(chunk_hash);
+  // file manager uploads replace existing files as before
+  request.setOnConflict(ConflictPolicy.OVERWRITE);
   await client.uploadFileChunk(request, null);
 }
 